@@ -0,0 +1,17 @@
+use std::process::Command;
+
+/// Embed the commit the proxy was built from as `GIT_HASH`, when built from a git checkout
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    let output = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output();
+    if let Ok(output) = output {
+        let hash = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if output.status.success() && !hash.is_empty() {
+            println!("cargo:rustc-env=GIT_HASH={}", hash);
+        }
+    }
+}
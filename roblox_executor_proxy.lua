@@ -5,16 +5,31 @@
 
     Configuration:
     - Change WS_HOST and WS_PORT to match your server settings
+    - Set CLIENT_NAME to tell this executor apart from others in the server's logs
+    - Add CLIENT_TAGS to receive scripts sent to those tags
+    - Set CLIENT_UUID to keep the same identity in /clients across game reloads
+    - Set SKIP_REPEATS to ignore a script identical to the last one this executor ran
+    - Set USE_TLS when the server is started with --tls-cert and --tls-key
+    - With --single-port, set WS_PORT to the HTTP port and WS_PATH to "/ws"
+    - With --base-path, put the same prefix at the start of WS_PATH
 ]]
 
 -- Configuration
 local WS_HOST = "localhost"
 local WS_PORT = 13378
 local RECONNECT_DELAY = 5
+local CLIENT_NAME = nil
+local CLIENT_TAGS = {}
+local CLIENT_UUID = nil
+local SKIP_REPEATS = false
+local USE_TLS = false
+local WS_PATH = ""
 
 -- Globals
-local url = ("ws://%s:%d"):format(WS_HOST, WS_PORT)
+local PROTOCOL = 1
+local url = ("%s://%s:%d%s"):format(USE_TLS and "wss" or "ws", WS_HOST, WS_PORT, WS_PATH)
 local ws = nil
+local lastHash = nil
 
 -- Services
 local HttpService = game:GetService("HttpService")
@@ -31,30 +46,96 @@ local function elog(err)
     warn("[Executor Proxy Error]: " .. err)
 end
 
-local function executeMessages()
-    ws.OnMessage:Connect(function(message)
-        local data = HttpService:JSONDecode(message)
-
-        if data.type == "ping" then
-            -- Keep-alive mechanism
-            ws:Send(HttpService:JSONEncode({type = "pong"}))
-        elseif data.type == "execute" then
-            local func, err = loadstring(data.script)
-
-            if not func then
-                -- Unable to load script
-                elog(err)
-                return
-            else
-                -- Execute and propagate runtime errors
-                local success, err = pcall(func)
-                if not success then
-                    elog(err)
-                    return
-                end
-            end
+-- Tell the server whether a script ran so it can report real completions
+local function sendAck(data, status, err)
+    ws:Send(HttpService:JSONEncode({
+        type = "ack",
+        id = data.id,
+        filename = data.filename,
+        status = status,
+        error = err
+    }))
+end
+
+-- Load and run a chunk, acking whether it ran
+local function runChunk(data, source)
+    if SKIP_REPEATS and data.sha256 and data.sha256 == lastHash then
+        log("Skipped " .. tostring(data.filename) .. ", it matches the last script run")
+        sendAck(data, "ok")
+        return
+    end
+    lastHash = data.sha256
+
+    local func, err = loadstring(source)
+
+    if not func then
+        -- Unable to load script
+        elog(err)
+        sendAck(data, "error", err)
+        return
+    end
+
+    -- Execute and propagate runtime errors
+    local success, err = pcall(func)
+    if not success then
+        elog(err)
+        sendAck(data, "error", tostring(err))
+        return
+    end
+    sendAck(data, "ok")
+end
+
+-- Executors expose base64 decoding under different names, if at all
+local base64Decode = (crypt and (crypt.base64decode or (crypt.base64 and crypt.base64.decode)))
+    or (syn and syn.crypt and syn.crypt.base64 and syn.crypt.base64.decode)
+
+-- Pieces of chunked messages still being received, by message ID
+local chunks = {}
+
+local function handleMessage(message)
+    local data = HttpService:JSONDecode(message)
+
+    if data.type == "ping" then
+        -- Keep-alive mechanism, echoing the timestamp so the server can measure latency
+        ws:Send(HttpService:JSONEncode({type = "pong", ts = data.ts}))
+    elseif data.type == "error" then
+        -- Server refused or is closing the connection
+        elog(data.reason)
+    elseif data.type == "shutdown" then
+        -- Server is stopping; close cleanly and wait to reconnect
+        log("Server is shutting down")
+        ws:Close()
+    elseif data.type == "execute_chunk" then
+        -- Part of a message too large for one frame; handle it once the end marker arrives
+        local pieces = chunks[data.id] or {}
+        chunks[data.id] = pieces
+        if data.seq < data.total then
+            pieces[data.seq + 1] = data.data
+            return
         end
-    end)
+        chunks[data.id] = nil
+        if #pieces ~= data.total then
+            elog(("Missing pieces of chunked message %d"):format(data.id))
+            return
+        end
+        handleMessage(table.concat(pieces))
+    elseif data.type == "execute" then
+        runChunk(data, data.script)
+    elseif data.type == "execute_binary" then
+        -- Precompiled bytecode, only runnable on executors that load it
+        if not base64Decode then
+            local err = "This executor cannot decode bytecode"
+            elog(err)
+            sendAck(data, "error", err)
+            return
+        end
+        runChunk(data, base64Decode(data.script_b64))
+    end
+end
+
+local function executeMessages()
+    chunks = {}
+    ws.OnMessage:Connect(handleMessage)
 
     -- Wait if the executor supports OnClose:Wait()
     local success, _ = pcall(function()
@@ -90,6 +171,14 @@ repeat
 
     log("Connected to server at " .. url)
 
+    ws:Send(HttpService:JSONEncode({
+        type = "hello",
+        name = CLIENT_NAME,
+        tags = #CLIENT_TAGS > 0 and CLIENT_TAGS or nil,
+        client_uuid = CLIENT_UUID,
+        protocol = PROTOCOL
+    }))
+
     executeMessages()
 
     ws = nil
@@ -0,0 +1,111 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use warp::http::StatusCode;
+use warp::reject::PayloadTooLarge;
+use warp::{Filter, Rejection, Reply};
+
+use crate::client_ip::optional_header;
+use crate::config::ServerConfig;
+use crate::live_config::{BodyTooLarge, LengthRequired};
+use crate::rate_limit::RateLimited;
+use crate::types::ExecuteResponse;
+
+/// Rejection for requests without the configured API key
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// Check the key sent in `Authorization: Bearer KEY` or `X-API-Key` against the configured one
+/// Every request is authorized when no API key is configured
+pub fn is_authorized(
+    authorization: Option<&str>,
+    api_key_header: Option<&str>,
+    config: &ServerConfig,
+) -> bool {
+    let Some(expected) = &config.api_key else {
+        return true;
+    };
+
+    let provided = authorization
+        .and_then(|value| value.trim().strip_prefix("Bearer "))
+        .or(api_key_header);
+    provided.is_some_and(|key| constant_time_eq(key.trim().as_bytes(), expected.as_bytes()))
+}
+
+/// Compare two byte strings in time that depends only on their lengths
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Filter extracting whether the request carries the configured API key
+pub fn authorized(
+    config: Arc<ServerConfig>,
+) -> impl Filter<Extract = (bool,), Error = Infallible> + Clone {
+    optional_header("authorization")
+        .and(optional_header("x-api-key"))
+        .map(
+            move |authorization: Option<String>, api_key: Option<String>| {
+                is_authorized(authorization.as_deref(), api_key.as_deref(), &config)
+            },
+        )
+}
+
+/// Filter rejecting requests without the configured API key before they reach the handler
+pub fn require_api_key(
+    config: Arc<ServerConfig>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    authorized(config)
+        .and_then(|authorized: bool| async move {
+            if authorized {
+                Ok(())
+            } else {
+                Err(warp::reject::custom(Unauthorized))
+            }
+        })
+        .untuple_one()
+}
+
+/// Turn `Unauthorized`, rate limit, oversized and unsized body rejections into JSON responses,
+/// passing other rejections through
+pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Rejection> {
+    let (error, status) = if err.find::<Unauthorized>().is_some() {
+        (
+            "Missing or invalid API key".to_string(),
+            StatusCode::UNAUTHORIZED,
+        )
+    } else if let Some(limited) = err.find::<RateLimited>() {
+        (
+            format!(
+                "Rate limit exceeded, retry in {} seconds",
+                limited.retry_after_secs
+            ),
+            StatusCode::TOO_MANY_REQUESTS,
+        )
+    } else if err.find::<PayloadTooLarge>().is_some() || err.find::<BodyTooLarge>().is_some() {
+        (
+            "Script is over the size limit".to_string(),
+            StatusCode::PAYLOAD_TOO_LARGE,
+        )
+    } else if err.find::<LengthRequired>().is_some() {
+        (
+            "A Content-Length header is required".to_string(),
+            StatusCode::LENGTH_REQUIRED,
+        )
+    } else {
+        return Err(err);
+    };
+
+    let response = ExecuteResponse {
+        error: Some(error),
+        ..Default::default()
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        status,
+    ))
+}
@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+
+/// Number of recent broadcasts the failure rate is computed over
+const WINDOW_SIZE: usize = 10;
+
+/// Minimum number of broadcasts in the window before the breaker can open
+const MIN_BROADCASTS: usize = 3;
+
+/// Current state of the circuit breaker
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BreakerState {
+    /// Broadcasts flow normally
+    Closed,
+    /// Broadcasts are refused until the cooldown has passed
+    Open,
+    /// Cooldown has passed; a single trial broadcast decides whether to close or reopen
+    HalfOpen,
+}
+
+impl BreakerState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half_open",
+        }
+    }
+}
+
+struct BreakerInner {
+    state: BreakerState,
+    /// (failed, total) for each recent broadcast
+    recent: VecDeque<(usize, usize)>,
+    opened_at: Option<Instant>,
+}
+
+/// Pauses broadcasts when too many clients fail to receive them
+pub struct CircuitBreaker {
+    threshold: f64,
+    cooldown: Duration,
+    inner: Mutex<BreakerInner>,
+    /// Set while a broadcast is testing recovery in the half-open state
+    trial_in_flight: AtomicBool,
+}
+
+/// Held by the one broadcast let through while the breaker is half-open
+/// Other broadcasts are refused until it is dropped, and if it ends without an outcome being
+/// recorded, e.g. because no clients were connected, the next broadcast becomes the trial
+pub struct BreakerTrial<'a> {
+    trial_in_flight: &'a AtomicBool,
+}
+
+impl Drop for BreakerTrial<'_> {
+    fn drop(&mut self) {
+        self.trial_in_flight.store(false, Ordering::Release);
+    }
+}
+
+impl CircuitBreaker {
+    /// Create a breaker that opens when the failure rate exceeds `threshold` (0-1)
+    pub fn new(threshold: f64, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            inner: Mutex::new(BreakerInner {
+                state: BreakerState::Closed,
+                recent: VecDeque::with_capacity(WINDOW_SIZE),
+                opened_at: None,
+            }),
+            trial_in_flight: AtomicBool::new(false),
+        }
+    }
+
+    /// Check whether a broadcast may proceed
+    /// While half-open only one broadcast at a time is let through, and it gets a trial to hold
+    /// until its outcome is recorded
+    /// Returns the seconds left in the cooldown when the breaker is open, or 1 while another
+    /// broadcast is the trial
+    pub async fn check(&self) -> Result<Option<BreakerTrial<'_>>, u64> {
+        let mut inner = self.inner.lock().await;
+        match inner.state {
+            BreakerState::Closed => return Ok(None),
+            BreakerState::Open => {
+                let elapsed = inner.opened_at.map(|t| t.elapsed()).unwrap_or_default();
+                if elapsed < self.cooldown {
+                    return Err((self.cooldown - elapsed).as_secs().max(1));
+                }
+                inner.state = BreakerState::HalfOpen;
+            }
+            BreakerState::HalfOpen => {}
+        }
+
+        if self.trial_in_flight.swap(true, Ordering::AcqRel) {
+            return Err(1);
+        }
+        Ok(Some(BreakerTrial {
+            trial_in_flight: &self.trial_in_flight,
+        }))
+    }
+
+    /// Record the outcome of a broadcast and return the resulting state
+    pub async fn record(&self, successful: usize, total: usize) -> BreakerState {
+        let mut inner = self.inner.lock().await;
+        if total == 0 {
+            return inner.state;
+        }
+        let failed = total - successful;
+
+        match inner.state {
+            BreakerState::HalfOpen => {
+                if failed as f64 / total as f64 > self.threshold {
+                    inner.state = BreakerState::Open;
+                    inner.opened_at = Some(Instant::now());
+                } else {
+                    inner.state = BreakerState::Closed;
+                    inner.recent.clear();
+                }
+            }
+            BreakerState::Closed => {
+                if inner.recent.len() == WINDOW_SIZE {
+                    inner.recent.pop_front();
+                }
+                inner.recent.push_back((failed, total));
+
+                let (window_failed, window_total) = inner
+                    .recent
+                    .iter()
+                    .fold((0, 0), |(f, t), (failed, total)| (f + failed, t + total));
+                if inner.recent.len() >= MIN_BROADCASTS
+                    && window_failed as f64 / window_total as f64 > self.threshold
+                {
+                    inner.state = BreakerState::Open;
+                    inner.opened_at = Some(Instant::now());
+                    inner.recent.clear();
+                }
+            }
+            BreakerState::Open => {}
+        }
+
+        inner.state
+    }
+
+    /// Get the current state
+    pub async fn state(&self) -> BreakerState {
+        self.inner.lock().await.state
+    }
+}
@@ -0,0 +1,84 @@
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use warp::Filter;
+
+use crate::config::ServerConfig;
+
+/// Resolve the caller's IP address
+///
+/// Forwarded headers are only honored when `trust_forwarded` is enabled and the socket peer is a
+/// trusted proxy, so arbitrary callers can't spoof their address. X-Forwarded-For is walked from
+/// right to left, skipping trusted proxies, and falls back to X-Real-IP and then the peer address.
+pub fn resolve_client_ip(
+    peer: Option<SocketAddr>,
+    forwarded_for: Option<&str>,
+    real_ip: Option<&str>,
+    config: &ServerConfig,
+) -> Option<IpAddr> {
+    let peer_ip = peer?.ip();
+    if !config.trust_forwarded || !is_trusted_proxy(peer_ip, config) {
+        return Some(peer_ip);
+    }
+
+    if let Some(forwarded_for) = forwarded_for {
+        let chain: Vec<IpAddr> = forwarded_for
+            .split(',')
+            .filter_map(|part| part.trim().parse().ok())
+            .collect();
+
+        // The first untrusted hop from the right is the real client; if every hop is a
+        // trusted proxy, the leftmost address is the original caller
+        let client = chain
+            .iter()
+            .rev()
+            .find(|ip| !is_trusted_proxy(**ip, config))
+            .or(chain.first());
+        if let Some(client) = client {
+            return Some(*client);
+        }
+    }
+
+    real_ip
+        .and_then(|value| value.trim().parse().ok())
+        .or(Some(peer_ip))
+}
+
+/// Whether forwarded headers from this address may be trusted
+/// With no trusted proxies configured, only loopback addresses are trusted
+fn is_trusted_proxy(ip: IpAddr, config: &ServerConfig) -> bool {
+    if config.trusted_proxies.is_empty() {
+        ip.is_loopback()
+    } else {
+        config.trusted_proxies.contains(&ip)
+    }
+}
+
+/// Filter extracting the socket peer address and the resolved caller IP
+pub fn client_ip(
+    config: Arc<ServerConfig>,
+) -> impl Filter<Extract = (Option<SocketAddr>, Option<IpAddr>), Error = Infallible> + Clone {
+    warp::addr::remote()
+        .and(optional_header("x-forwarded-for"))
+        .and(optional_header("x-real-ip"))
+        .map(
+            move |peer: Option<SocketAddr>,
+                  forwarded_for: Option<String>,
+                  real_ip: Option<String>| {
+                let ip =
+                    resolve_client_ip(peer, forwarded_for.as_deref(), real_ip.as_deref(), &config);
+                (peer, ip)
+            },
+        )
+        .untuple_one()
+}
+
+/// Extract a header as a string, treating missing or non-UTF-8 values as absent
+pub(crate) fn optional_header(
+    name: &'static str,
+) -> impl Filter<Extract = (Option<String>,), Error = Infallible> + Clone {
+    warp::header::optional::<String>(name)
+        .or(warp::any().map(|| None))
+        .unify()
+}
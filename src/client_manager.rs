@@ -1,179 +1,1485 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use chrono::Local;
-use tokio::sync::Mutex;
+use chrono::{Local, Utc};
+use futures_util::future::join_all;
+use tokio::process::Command;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, Sender, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{broadcast, Mutex, Notify, RwLock, RwLockWriteGuard, Semaphore, SemaphorePermit};
 use warp::ws::Message;
 
+use crate::circuit_breaker::{BreakerState, BreakerTrial, CircuitBreaker};
+use crate::config::{
+    split_command, LogFormat, OnEmpty, ServerConfig, BASE_PROTOCOL_VERSION, DEFAULT_CLIENT_BUFFER,
+    DEFAULT_HISTORY_SIZE, EVENT_BUFFER, MAX_CLIENT_NAME_CHARS, MAX_CLIENT_UUID_CHARS,
+    MAX_PING_INTERVAL_SECS, MAX_REMEMBERED_UUIDS, MIN_PING_INTERVAL_SECS, PING_INTERVAL_SECS,
+    QUEUE_CAPACITY, REPLAY_MAX_AGE_SECS, SEND_RETRY_DELAY_MS,
+};
+use crate::debounce::Debouncer;
+use crate::in_flight::{InFlight, InFlightGuard};
+use crate::log_file::LogFile;
+use crate::metrics::Metrics;
+use crate::timestamp::Timestamp;
+use crate::types::{
+    ClientCountMessage, ClientInfo, HistoryEntry, PingMessage, ResultMessage, ResultReport,
+    ServerEvent, WelcomeMessage,
+};
+use crate::webhook::ResultWebhook;
+
+/// WebSocket close code for a normal closure
+const CLOSE_NORMAL: u16 = 1000;
+
+/// Close reason sent to clients that stopped answering pings
+pub const PING_TIMEOUT_CLOSE_REASON: &str = "ping timeout";
+
+/// Close reason sent to clients kicked through /disconnect
+pub const KICKED_CLOSE_REASON: &str = "kicked";
+
+/// Close reason sent to a connection when a newer one claims its UUID
+pub const REPLACED_CLOSE_REASON: &str = "replaced by a newer connection";
+
+/// Event types published to /events subscribers
+pub const CLIENT_CONNECTED_EVENT: &str = "client_connected";
+pub const CLIENT_DISCONNECTED_EVENT: &str = "client_disconnected";
+pub const CLIENT_TIMEOUT_EVENT: &str = "client_timeout";
+pub const SCRIPT_BROADCAST_EVENT: &str = "script_broadcast";
+
+/// External commands spawned when clients connect or disconnect
+/// Each command receives the client ID as an argument, followed on disconnect by the name the
+/// client gave in its hello, if any
+#[derive(Clone, Default)]
+pub struct ConnectionHooks {
+    pub on_connect: Option<String>,
+    pub on_disconnect: Option<String>,
+}
+
+/// Everything tracked about a single connected client
+struct ClientEntry {
+    sender: Sender<Message>,
+    connected_at: Instant,
+    last_pong: Instant,
+    last_ping: Instant,
+    last_targeted: Option<Instant>,
+    /// Ping interval negotiated by the client, if it asked for one
+    ping_interval: Option<u64>,
+    /// Human-readable name sent in the client's hello
+    name: Option<String>,
+    /// Groups the client joined in its hello
+    tags: Vec<String>,
+    /// Remote address the client connected from, when the server could tell
+    address: Option<SocketAddr>,
+    /// Stable identity the client claimed in its hello, kept across reconnects
+    uuid: Option<String>,
+    /// Round-trip time of the latest ping whose timestamp the client echoed
+    rtt_ms: Option<u64>,
+    /// Protocol version reported in the client's hello
+    protocol: Option<u32>,
+}
+
+impl ClientEntry {
+    fn ping_interval(&self, default_secs: u64) -> u64 {
+        self.ping_interval.unwrap_or(default_secs)
+    }
+
+    /// How the client appears in log lines: its name and ID, or just the ID if unnamed
+    fn label(&self, id: usize) -> String {
+        match &self.name {
+            Some(name) => format!("{} ({})", name, id),
+            None => id.to_string(),
+        }
+    }
+}
+
+/// Name and tags remembered for a client UUID while it's disconnected
+#[derive(Clone)]
+struct ClientProfile {
+    name: Option<String>,
+    tags: Vec<String>,
+}
+
+/// Connected clients and the counter used to assign their IDs
+#[derive(Default)]
+struct ClientState {
+    clients: HashMap<usize, ClientEntry>,
+    next_id: usize,
+    /// Metadata of clients that claimed a UUID, restored when the UUID reconnects
+    profiles: HashMap<String, ClientProfile>,
+}
+
+impl ClientState {
+    /// Remove clients, skipping IDs that are already gone, and remember the metadata of those
+    /// with a UUID
+    /// With a `close_reason`, each removed client is sent a normal close frame carrying it first
+    fn take_clients(&mut self, ids: &[usize], close_reason: Option<&str>) -> Vec<RemovedClient> {
+        ids.iter()
+            .filter_map(|id| {
+                let entry = self.clients.remove(id)?;
+                if let Some(reason) = close_reason {
+                    // The channel may already be closed if the connection just went away, or full
+                    // if the client stopped reading
+                    let _ = entry
+                        .sender
+                        .try_send(Message::close_with(CLOSE_NORMAL, reason.to_string()));
+                }
+                if let Some(uuid) = &entry.uuid {
+                    if self.profiles.len() < MAX_REMEMBERED_UUIDS
+                        || self.profiles.contains_key(uuid)
+                    {
+                        self.profiles.insert(
+                            uuid.clone(),
+                            ClientProfile {
+                                name: entry.name.clone(),
+                                tags: entry.tags.clone(),
+                            },
+                        );
+                    }
+                }
+                Some(RemovedClient {
+                    id: *id,
+                    label: entry.label(*id),
+                    uptime_secs: entry.connected_at.elapsed().as_secs(),
+                    name: entry.name,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A client just removed from the state, with what's needed to report its departure
+struct RemovedClient {
+    id: usize,
+    /// How the client appears in log lines
+    label: String,
+    uptime_secs: u64,
+    name: Option<String>,
+}
+
+/// Messages held while no clients are connected, oldest first, each as the frames it is sent in
+struct MessageQueue {
+    messages: Mutex<VecDeque<(Instant, Vec<Message>)>>,
+    ttl: Duration,
+}
+
 /// Manages WebSocket client connections and message broadcasting
 pub struct ClientManager {
-    clients: Arc<Mutex<HashSet<usize>>>,
-    next_id: Arc<Mutex<usize>>,
-    senders: Arc<Mutex<HashMap<usize, tokio::sync::mpsc::UnboundedSender<Message>>>>,
-    last_pong: Arc<Mutex<HashMap<usize, Instant>>>,
+    state: Arc<RwLock<ClientState>>,
+    hooks: ConnectionHooks,
+    draining: AtomicBool,
+    circuit_breaker: Option<CircuitBreaker>,
+    next_execution_id: AtomicU64,
+    pending_acks: std::sync::Mutex<HashMap<u64, UnboundedSender<usize>>>,
+    queue: Option<MessageQueue>,
+    metrics: Metrics,
+    started_at: Instant,
+    result_webhook: Option<ResultWebhook>,
+    debouncer: Option<Debouncer>,
+    /// Files being executed right now, claimed for the length of each request
+    in_flight: InFlight,
+    /// Limits how many execute requests broadcast at once, unlimited when unset
+    broadcast_permits: Option<Semaphore>,
+    /// Ping interval for clients that don't negotiate their own
+    default_ping_interval: u64,
+    /// Tell clients the connected count whenever it changes
+    broadcast_count: bool,
+    /// Recent broadcasts, oldest first, capped at `history_size`
+    history: Mutex<VecDeque<HistoryEntry>>,
+    history_size: usize,
+    /// Retry failed sends once before removing the client
+    retry_sends: bool,
+    /// Connections beyond this many clients are refused
+    max_clients: Option<usize>,
+    /// Messages each client's channel holds before it counts as a slow consumer
+    client_buffer: usize,
+    /// Remove slow consumers instead of only skipping them
+    drop_slow_clients: bool,
+    /// Most recent script broadcast to every client, kept for /replay
+    last_script: Mutex<Option<LastScript>>,
+    /// Send newly connected clients the last script if it's recent enough
+    replay_last: bool,
+    /// Sent to each client as soon as it connects, and the base of hello replies
+    welcome: Option<WelcomeMessage>,
+    /// Action taken when the last client disconnects
+    on_empty: OnEmpty,
+    /// Notified to shut the server down when `on_empty` is `Exit`
+    shutdown: Option<Arc<Notify>>,
+    /// Activity published to /events subscribers, taken at shutdown to end their streams
+    events: std::sync::Mutex<Option<broadcast::Sender<ServerEvent>>>,
+}
+
+/// The last script broadcast to every client, as the frames it was sent in
+struct LastScript {
+    filename: String,
+    sent_at: Instant,
+    frames: Vec<Message>,
+}
+
+/// Acks for one execute message, yielding the ID of each client that acks it
+/// Dropping it stops the collection, so a request that times out or is cancelled mid-wait
+/// leaves nothing behind and later acks are ignored
+pub struct AckWait<'a> {
+    client_manager: &'a ClientManager,
+    execution_id: u64,
+    acks: UnboundedReceiver<usize>,
+}
+
+impl AckWait<'_> {
+    /// Wait for the next client to ack
+    pub async fn recv(&mut self) -> Option<usize> {
+        self.acks.recv().await
+    }
+}
+
+impl Drop for AckWait<'_> {
+    fn drop(&mut self) {
+        self.client_manager
+            .pending_acks
+            .lock()
+            .unwrap()
+            .remove(&self.execution_id);
+    }
+}
+
+/// Why frames couldn't be handed to a client's channel
+#[derive(Clone, Copy)]
+enum SendFailure {
+    /// The client isn't reading fast enough and its buffer is full
+    Full,
+    /// The connection is gone
+    Closed,
 }
 
 impl ClientManager {
     /// Create a new client manager
     pub fn new() -> Self {
         Self {
-            clients: Arc::new(Mutex::new(HashSet::new())),
-            next_id: Arc::new(Mutex::new(0)),
-            senders: Arc::new(Mutex::new(HashMap::new())),
-            last_pong: Arc::new(Mutex::new(HashMap::new())),
+            state: Arc::new(RwLock::new(ClientState::default())),
+            hooks: ConnectionHooks::default(),
+            draining: AtomicBool::new(false),
+            circuit_breaker: None,
+            next_execution_id: AtomicU64::new(0),
+            pending_acks: std::sync::Mutex::new(HashMap::new()),
+            queue: None,
+            metrics: Metrics::default(),
+            started_at: Instant::now(),
+            result_webhook: None,
+            debouncer: None,
+            in_flight: InFlight::new(),
+            broadcast_permits: None,
+            default_ping_interval: PING_INTERVAL_SECS,
+            broadcast_count: false,
+            history: Mutex::new(VecDeque::new()),
+            history_size: DEFAULT_HISTORY_SIZE,
+            retry_sends: false,
+            max_clients: None,
+            client_buffer: DEFAULT_CLIENT_BUFFER,
+            drop_slow_clients: false,
+            last_script: Mutex::new(None),
+            replay_last: false,
+            welcome: None,
+            on_empty: OnEmpty::None,
+            shutdown: None,
+            events: std::sync::Mutex::new(Some(broadcast::channel(EVENT_BUFFER).0)),
         }
     }
 
-    /// Register a new client and return its ID
-    pub async fn register(&self, sender: tokio::sync::mpsc::UnboundedSender<Message>) -> usize {
-        let mut id_guard = self.next_id.lock().await;
-        let id = *id_guard;
-        *id_guard += 1;
-        drop(id_guard);
+    /// Set the commands to run when clients connect or disconnect
+    pub fn with_hooks(mut self, hooks: ConnectionHooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Pause broadcasts when too many clients fail to receive them
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    /// Hold messages sent while no clients are connected for the next client, for up to `ttl`
+    pub fn with_queue(mut self, ttl: Duration) -> Self {
+        self.queue = Some(MessageQueue {
+            messages: Mutex::new(VecDeque::with_capacity(QUEUE_CAPACITY)),
+            ttl,
+        });
+        self
+    }
+
+    /// Ping clients that don't negotiate their own interval every `secs` seconds
+    pub fn with_ping_interval(mut self, secs: u64) -> Self {
+        self.default_ping_interval = secs;
+        self
+    }
+
+    /// Drop scripts identical to the last one sent within `window`
+    pub fn with_debounce(mut self, window: Duration) -> Self {
+        self.debouncer = Some(Debouncer::new(window));
+        self
+    }
+
+    /// Forward script results reported by clients to a webhook
+    pub fn with_result_webhook(mut self, webhook: ResultWebhook) -> Self {
+        self.result_webhook = Some(webhook);
+        self
+    }
+
+    /// Send every client a `client_count` message whenever a client connects or disconnects
+    pub fn with_count_broadcast(mut self) -> Self {
+        self.broadcast_count = true;
+        self
+    }
+
+    /// Keep the last `size` broadcasts for /history, or none when 0
+    pub fn with_history_size(mut self, size: usize) -> Self {
+        self.history_size = size;
+        self
+    }
+
+    /// Retry a failed send once after a short delay before removing the client, so clients
+    /// that are only briefly unreachable aren't dropped
+    pub fn with_send_retry(mut self) -> Self {
+        self.retry_sends = true;
+        self
+    }
+
+    /// Refuse connections in `try_register` once `max` clients are connected
+    pub fn with_max_clients(mut self, max: usize) -> Self {
+        self.max_clients = Some(max);
+        self
+    }
+
+    /// Let at most `max` execute requests read, serialize and broadcast their scripts at once
+    pub fn with_max_concurrent_broadcasts(mut self, max: usize) -> Self {
+        self.broadcast_permits = Some(Semaphore::new(max));
+        self
+    }
+
+    /// Size each client's channel to hold `capacity` messages
+    pub fn with_client_buffer(mut self, capacity: usize) -> Self {
+        self.client_buffer = capacity;
+        self
+    }
+
+    /// Remove clients whose buffer is full during a broadcast instead of only skipping them
+    pub fn with_slow_client_drop(mut self) -> Self {
+        self.drop_slow_clients = true;
+        self
+    }
+
+    /// Send each newly connected client the last broadcast script, if one was sent in the last
+    /// `REPLAY_MAX_AGE_SECS` seconds
+    pub fn with_replay_last(mut self) -> Self {
+        self.replay_last = true;
+        self
+    }
+
+    /// Greet every client with this message as it connects, before anything queued or replayed
+    pub fn with_welcome(mut self, welcome: WelcomeMessage) -> Self {
+        self.welcome = Some(welcome);
+        self
+    }
+
+    /// Act once the last client disconnects, notifying `shutdown` when the action is `Exit`
+    pub fn with_on_empty(mut self, on_empty: OnEmpty, shutdown: Arc<Notify>) -> Self {
+        self.on_empty = on_empty;
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Welcome message for a client using `ping_interval`, as sent in reply to its hello
+    pub fn welcome(&self, ping_interval: u64) -> WelcomeMessage {
+        let mut welcome = self
+            .welcome
+            .clone()
+            .unwrap_or_else(|| ServerConfig::default().welcome_message());
+        welcome.ping_interval = ping_interval;
+        welcome
+    }
 
-        let mut clients = self.clients.lock().await;
-        clients.insert(id);
-        let count = clients.len();
-        drop(clients);
+    /// Capacity to create client channels with
+    pub fn client_buffer(&self) -> usize {
+        self.client_buffer
+    }
+
+    /// Receive server activity as it happens, starting from now
+    /// The receiver is closed once the server shuts down
+    pub fn subscribe_events(&self) -> broadcast::Receiver<ServerEvent> {
+        match self.events.lock().unwrap().as_ref() {
+            Some(events) => events.subscribe(),
+            None => broadcast::channel(1).1,
+        }
+    }
 
-        let mut senders = self.senders.lock().await;
-        senders.insert(id, sender);
-        drop(senders);
+    /// Publish an event to /events subscribers, if there are any
+    fn publish(&self, event_type: &str, payload: serde_json::Value) {
+        let Some(events) = self.events.lock().unwrap().clone() else {
+            return;
+        };
+        // Sending only fails when nobody is subscribed
+        let _ = events.send(ServerEvent {
+            event_type: event_type.to_string(),
+            timestamp: Timestamp::now(),
+            payload,
+        });
+    }
 
-        let mut last_pong = self.last_pong.lock().await;
-        last_pong.insert(id, Instant::now());
-        drop(last_pong);
+    /// Register a new client and return its ID, regardless of the client limit
+    pub async fn register(&self, sender: Sender<Message>) -> usize {
+        let state = self.state.write().await;
+        self.add_client(state, sender, None).await
+    }
 
-        log(&format!("Client connected. Total clients: {}", count));
+    /// Register a new client and return its ID, unless the client limit has been reached
+    /// The count is checked under the same lock the client is added with, so concurrent
+    /// connections can't overshoot the limit
+    /// Returns the current client count when full
+    pub async fn try_register(
+        &self,
+        sender: Sender<Message>,
+        address: Option<SocketAddr>,
+    ) -> Result<usize, usize> {
+        let state = self.state.write().await;
+        let count = state.clients.len();
+        if self.max_clients.is_some_and(|max| count >= max) {
+            return Err(count);
+        }
+        Ok(self.add_client(state, sender, address).await)
+    }
+
+    /// Add a client while holding the state lock, releasing it before logging and hooks
+    async fn add_client(
+        &self,
+        mut state: RwLockWriteGuard<'_, ClientState>,
+        sender: Sender<Message>,
+        address: Option<SocketAddr>,
+    ) -> usize {
+        let id = state.next_id;
+        state.next_id += 1;
+
+        // Greet the client first, so it knows the server's settings before any script arrives
+        if let Some(welcome) = &self.welcome {
+            if let Ok(json) = serde_json::to_string(welcome) {
+                let _ = send_all(&sender, &[Message::text(json)]);
+            }
+        }
+
+        // Hand over anything queued while nobody was connected. A message that doesn't fit in
+        // the client's buffer stays queued, with everything after it, for the next client
+        let mut delivered = 0;
+        let mut kept = 0;
+        if let Some(queue) = &self.queue {
+            let mut messages = queue.messages.lock().await;
+            messages.retain(|(queued_at, _)| queued_at.elapsed() <= queue.ttl);
+            while let Some((_, frames)) = messages.front() {
+                if send_all(&sender, frames).is_err() {
+                    break;
+                }
+                messages.pop_front();
+                delivered += 1;
+            }
+            kept = messages.len();
+        }
+
+        // Otherwise catch the client up on the last script, e.g. after a game reload
+        let mut replayed = None;
+        if self.replay_last && delivered == 0 {
+            let last_script = self.last_script.lock().await;
+            if let Some(last) = last_script
+                .as_ref()
+                .filter(|last| last.sent_at.elapsed() <= Duration::from_secs(REPLAY_MAX_AGE_SECS))
+            {
+                if send_all(&sender, &last.frames).is_ok() {
+                    replayed = Some(last.filename.clone());
+                }
+            }
+        }
+
+        let now = Instant::now();
+        state.clients.insert(
+            id,
+            ClientEntry {
+                sender,
+                connected_at: now,
+                last_pong: now,
+                last_ping: now,
+                last_targeted: None,
+                ping_interval: None,
+                name: None,
+                tags: Vec::new(),
+                address,
+                uuid: None,
+                rtt_ms: None,
+                protocol: None,
+            },
+        );
+        let count = state.clients.len();
+        drop(state);
+        self.metrics.record_connection();
+        self.publish(
+            CLIENT_CONNECTED_EVENT,
+            serde_json::json!({
+                "client_id": id,
+                "address": address.map(|address| address.to_string()),
+                "total_clients": count,
+            }),
+        );
+
+        match address {
+            Some(address) => log(&format!(
+                "Client {} connected from {}. Total clients: {}",
+                id, address, count
+            )),
+            None => log(&format!(
+                "Client {} connected. Total clients: {}",
+                id, count
+            )),
+        }
+        if delivered > 0 {
+            log(&format!(
+                "Delivered {} queued messages to client {}",
+                delivered, id
+            ));
+        }
+        if kept > 0 {
+            warn(&format!(
+                "Client {} couldn't take {} queued messages, keeping them for the next client",
+                id, kept
+            ));
+        }
+        if let Some(filename) = replayed {
+            log(&format!("Replayed '{}' to client {}", filename, id));
+        }
+        if let Some(command) = &self.hooks.on_connect {
+            run_hook(command, id, None);
+        }
+        self.announce_count().await;
         id
     }
 
+    /// Send the connected client count to every client, if enabled
+    /// Senders are collected first so nothing is sent while holding the clients lock, and
+    /// failed sends are left for the next broadcast or timeout to clean up
+    async fn announce_count(&self) {
+        if !self.broadcast_count {
+            return;
+        }
+
+        let state = self.state.read().await;
+        let count = state.clients.len();
+        let senders: Vec<Sender<Message>> = state
+            .clients
+            .values()
+            .map(|entry| entry.sender.clone())
+            .collect();
+        drop(state);
+
+        let message = ClientCountMessage {
+            msg_type: "client_count".to_string(),
+            count,
+        };
+        let Ok(json) = serde_json::to_string(&message) else {
+            return;
+        };
+        for sender in senders {
+            let _ = sender.try_send(Message::text(json.clone()));
+        }
+    }
+
     /// Unregister a client by ID
+    /// Does nothing if the client was already removed, e.g. by a timeout
     pub async fn unregister(&self, id: usize) {
-        let mut clients = self.clients.lock().await;
-        clients.remove(&id);
-        let count = clients.len();
-        drop(clients);
+        let (removed, count) = self.remove_clients(&[id], None).await;
+        if let Some(client) = removed.first() {
+            log(&format!(
+                "Client {} disconnected after {}s. Total clients: {}",
+                client.label, client.uptime_secs, count
+            ));
+        }
+    }
 
-        let mut senders = self.senders.lock().await;
-        senders.remove(&id);
-        drop(senders);
+    /// Remove clients, skipping IDs that are already gone
+    /// With a `close_reason`, each removed client is sent a normal close frame carrying it first
+    /// Returns the removed clients and the remaining client count
+    async fn remove_clients(
+        &self,
+        ids: &[usize],
+        close_reason: Option<&str>,
+    ) -> (Vec<RemovedClient>, usize) {
+        let mut state = self.state.write().await;
+        let removed = state.take_clients(ids, close_reason);
+        let count = state.clients.len();
+        drop(state);
 
-        let mut last_pong = self.last_pong.lock().await;
-        last_pong.remove(&id);
-        drop(last_pong);
+        self.finish_removal(&removed, close_reason, count).await;
+        (removed, count)
+    }
 
-        log(&format!("Client disconnected. Total clients: {}", count));
+    /// Run disconnect hooks, publish events and announce the new count once clients have been
+    /// removed
+    async fn finish_removal(
+        &self,
+        removed: &[RemovedClient],
+        close_reason: Option<&str>,
+        count: usize,
+    ) {
+        for client in removed {
+            self.publish(
+                CLIENT_DISCONNECTED_EVENT,
+                serde_json::json!({
+                    "client_id": client.id,
+                    "uptime_secs": client.uptime_secs,
+                    "reason": close_reason,
+                    "total_clients": count,
+                }),
+            );
+        }
+        if let Some(command) = &self.hooks.on_disconnect {
+            for client in removed {
+                run_hook(command, client.id, client.name.as_deref());
+            }
+        }
+        if !removed.is_empty() {
+            self.announce_count().await;
+        }
+        if !removed.is_empty() && count == 0 {
+            match self.on_empty {
+                OnEmpty::None => {}
+                OnEmpty::Log => warn("All clients have disconnected"),
+                OnEmpty::Exit => {
+                    warn("All clients have disconnected, shutting down");
+                    if let Some(shutdown) = &self.shutdown {
+                        // Stores a permit, so the shutdown isn't missed if nobody is waiting yet
+                        shutdown.notify_one();
+                    }
+                }
+            }
+        }
     }
 
     /// Broadcast a message to all connected clients
-    /// Returns (successful_count, total_count)
-    pub async fn broadcast(&self, message: &str) -> (usize, usize) {
-        let senders = self.senders.lock().await;
-        let total = senders.len();
+    /// Returns (successful_count, total_count, failed_ids, disconnected_ids)
+    pub async fn broadcast(&self, message: &str) -> (usize, usize, Vec<usize>, Vec<usize>) {
+        self.send_frame(None, Message::text(message)).await
+    }
+
+    /// Broadcast a message to the given clients only
+    /// IDs that are no longer connected are ignored and not counted in the total
+    /// Returns (successful_count, total_count, failed_ids, disconnected_ids)
+    pub async fn broadcast_to(
+        &self,
+        ids: &[usize],
+        message: &str,
+    ) -> (usize, usize, Vec<usize>, Vec<usize>) {
+        self.send_frame(Some(ids), Message::text(message)).await
+    }
+
+    /// Broadcast a message to the clients that registered a tag
+    /// Returns (successful_count, total_count, failed_ids, disconnected_ids)
+    pub async fn broadcast_to_tag(
+        &self,
+        tag: &str,
+        message: &str,
+    ) -> (usize, usize, Vec<usize>, Vec<usize>) {
+        let ids = self.client_ids_with_tag(tag).await;
+        self.broadcast_to(&ids, message).await
+    }
+
+    /// Send a message to a single client
+    /// Returns false if the client isn't connected or the send failed
+    pub async fn send_to(&self, id: usize, message: &str) -> bool {
+        let (successful, _, _, _) = self.send_frame(Some(&[id]), Message::text(message)).await;
+        successful == 1
+    }
+
+    /// Resend a frame to clients whose send just failed, after a short delay
+    /// Returns the IDs that received it this time and those that still failed or are gone
+    async fn retry_send(
+        &self,
+        ids: &[usize],
+        frames: &[Message],
+    ) -> (Vec<usize>, Vec<(usize, SendFailure)>) {
+        tokio::time::sleep(Duration::from_millis(SEND_RETRY_DELAY_MS)).await;
+
+        let state = self.state.read().await;
+        let targets: Vec<(usize, Option<Sender<Message>>)> = ids
+            .iter()
+            .map(|id| (*id, state.clients.get(id).map(|entry| entry.sender.clone())))
+            .collect();
+        drop(state);
+
+        let mut recovered = Vec::new();
+        let mut failed = Vec::new();
+        for (id, sender) in targets {
+            match sender.map(|sender| send_all(&sender, frames)) {
+                Some(Ok(())) => recovered.push(id),
+                Some(Err(failure)) => failed.push((id, failure)),
+                None => failed.push((id, SendFailure::Closed)),
+            }
+        }
+        (recovered, failed)
+    }
+
+    /// Send a WebSocket frame, text or binary, to the given clients or to every client when `ids`
+    /// is None. Senders are snapshotted under a short read lock so the sends hold no lock
+    /// Returns (successful_count, total_count, failed_ids, disconnected_ids)
+    pub async fn send_frame(
+        &self,
+        ids: Option<&[usize]>,
+        frame: Message,
+    ) -> (usize, usize, Vec<usize>, Vec<usize>) {
+        self.send_frames(ids, &[frame]).await
+    }
+
+    /// Send a message made up of several frames, like a chunked script, in order to each client
+    /// A client only counts as reached if every frame was sent to it. Clients whose buffer is
+    /// full are reported as failed too, and only removed with `with_slow_client_drop`. Clients
+    /// that were in the snapshot but whose connection closed before the send are reported
+    /// separately as disconnected, since that isn't a failure to deliver to a live client
+    /// Senders are cloned under a brief read lock and the sends happen after it is released, so
+    /// clients can connect and disconnect while a broadcast is in flight
+    pub async fn send_frames(
+        &self,
+        ids: Option<&[usize]>,
+        frames: &[Message],
+    ) -> (usize, usize, Vec<usize>, Vec<usize>) {
+        let state = self.state.read().await;
+        let targets: Vec<(usize, Sender<Message>)> = match ids {
+            Some(ids) => ids
+                .iter()
+                .filter_map(|id| Some((*id, state.clients.get(id)?.sender.clone())))
+                .collect(),
+            None => state
+                .clients
+                .iter()
+                .map(|(id, entry)| (*id, entry.sender.clone()))
+                .collect(),
+        };
+        drop(state);
+        let total = targets.len();
 
         if total == 0 {
-            return (0, 0);
+            return (0, 0, Vec::new(), Vec::new());
+        }
+
+        let results = join_all(
+            targets
+                .into_iter()
+                .map(|(id, sender)| async move { (id, send_all(&sender, frames)) }),
+        )
+        .await;
+
+        let mut delivered = Vec::new();
+        let mut failed = Vec::new();
+        for (id, result) in results {
+            match result {
+                Ok(()) => delivered.push(id),
+                Err(failure) => failed.push((id, failure)),
+            }
+        }
+
+        // Give clients a moment to recover before writing them off
+        if self.retry_sends && !failed.is_empty() {
+            let failed_ids: Vec<usize> = failed.iter().map(|(id, _)| *id).collect();
+            let (recovered, still_failed) = self.retry_send(&failed_ids, frames).await;
+            delivered.extend(recovered);
+            failed = still_failed;
         }
 
-        let mut successful = 0;
         let mut failed_ids = Vec::new();
+        let mut disconnected_ids = Vec::new();
+        let mut removed_ids = Vec::new();
+        for (id, failure) in failed {
+            match failure {
+                SendFailure::Full => {
+                    warn(&format!(
+                        "Client {} is a slow consumer, its buffer of {} messages is full",
+                        id, self.client_buffer
+                    ));
+                    if self.drop_slow_clients {
+                        removed_ids.push(id);
+                    }
+                    failed_ids.push(id);
+                }
+                SendFailure::Closed => {
+                    log(&format!("Client {} disconnected during broadcast", id));
+                    disconnected_ids.push(id);
+                    removed_ids.push(id);
+                }
+            }
+        }
+        let successful = delivered.len();
 
-        for (id, sender) in senders.iter() {
-            if sender.send(Message::text(message.to_string())).is_ok() {
-                successful += 1;
-            } else {
-                log(&format!("Failed to send to client {}", id));
-                failed_ids.push(*id);
+        // IDs are never reused, so clients registered since the snapshot can't be touched here
+        let mut state = self.state.write().await;
+        let now = Instant::now();
+        for id in &delivered {
+            if let Some(entry) = state.clients.get_mut(id) {
+                entry.last_targeted = Some(now);
             }
         }
-        drop(senders);
+        drop(state);
+
+        // Queued is not delivered: this only confirms the message reached each client's channel
+        match disconnected_ids.len() {
+            0 => log(&format!(
+                "Broadcast queued to {}/{} clients",
+                successful, total
+            )),
+            disconnected => log(&format!(
+                "Broadcast queued to {}/{} clients, {} disconnected during broadcast",
+                successful, total, disconnected
+            )),
+        }
 
-        // Remove failed clients
-        if !failed_ids.is_empty() {
-            let mut clients = self.clients.lock().await;
-            let mut senders = self.senders.lock().await;
-            for id in failed_ids {
-                clients.remove(&id);
-                senders.remove(&id);
+        if let Some(circuit_breaker) = &self.circuit_breaker {
+            let before = circuit_breaker.state().await;
+            // A client leaving mid-broadcast says nothing about the health of the others
+            let after = circuit_breaker
+                .record(successful, total - disconnected_ids.len())
+                .await;
+            if after != before {
+                if after == BreakerState::Open {
+                    warn("Circuit breaker opened: pausing broadcasts");
+                } else {
+                    log(&format!("Circuit breaker {}", after.as_str()));
+                }
             }
         }
 
-        (successful, total)
+        // Remove clients that are gone, and slow ones if asked to
+        if !removed_ids.is_empty() {
+            self.remove_clients(&removed_ids, None).await;
+        }
+
+        (successful, total, failed_ids, disconnected_ids)
+    }
+
+    /// Hold a message, as the frames it is sent in, for the next client to connect, dropping the
+    /// oldest if the queue is full
+    /// Returns false if queueing is disabled or a client is connected after all
+    pub async fn queue_message(&self, frames: Vec<Message>) -> bool {
+        let Some(queue) = &self.queue else {
+            return false;
+        };
+
+        // Holding the state lock keeps a client from registering between the check and the push
+        let state = self.state.read().await;
+        if !state.clients.is_empty() {
+            return false;
+        }
+        let mut messages = queue.messages.lock().await;
+        messages.retain(|(queued_at, _)| queued_at.elapsed() <= queue.ttl);
+        if messages.len() == QUEUE_CAPACITY {
+            messages.pop_front();
+        }
+        messages.push_back((Instant::now(), frames));
+        let waiting = messages.len();
+        drop(messages);
+        drop(state);
+
+        log(&format!(
+            "No clients connected, queued message ({} waiting)",
+            waiting
+        ));
+        true
+    }
+
+    /// Tell every client the server is going away, then close their connections
+    /// Event streams are ended too, since graceful shutdown waits for open responses
+    /// Returns the number of clients that were notified
+    pub async fn notify_shutdown(&self) -> usize {
+        let (notified, _, _, _) = self.broadcast(r#"{"type":"shutdown"}"#).await;
+        self.events.lock().unwrap().take();
+
+        let state = self.state.read().await;
+        for entry in state.clients.values() {
+            let _ = entry.sender.try_send(Message::close());
+        }
+        notified
+    }
+
+    /// Remember the frames of a script sent to every client for /replay and `with_replay_last`
+    pub async fn remember_script(&self, filename: &str, frames: Vec<Message>) {
+        *self.last_script.lock().await = Some(LastScript {
+            filename: filename.to_string(),
+            sent_at: Instant::now(),
+            frames,
+        });
+    }
+
+    /// Send the last script to every connected client again, however long ago it was sent
+    /// Returns its filename and (successful_count, total_count, failed_ids, disconnected_ids), or
+    /// None if no script has been sent yet
+    pub async fn replay(&self) -> Option<(String, (usize, usize, Vec<usize>, Vec<usize>))> {
+        let last_script = self.last_script.lock().await;
+        let last = last_script.as_ref()?;
+        let filename = last.filename.clone();
+        let frames = last.frames.clone();
+        drop(last_script);
+
+        Some((filename, self.send_frames(None, &frames).await))
+    }
+
+    /// Remember a broadcast, dropping the oldest once the history is full, and publish it to
+    /// /events subscribers
+    pub async fn record_history(&self, entry: HistoryEntry) {
+        if let Ok(payload) = serde_json::to_value(&entry) {
+            self.publish(SCRIPT_BROADCAST_EVENT, payload);
+        }
+        if self.history_size == 0 {
+            return;
+        }
+
+        let mut history = self.history.lock().await;
+        while history.len() >= self.history_size {
+            history.pop_front();
+        }
+        history.push_back(entry);
+    }
+
+    /// Recent broadcasts, oldest first
+    pub async fn history(&self) -> Vec<HistoryEntry> {
+        self.history.lock().await.iter().cloned().collect()
+    }
+
+    /// Allocate a correlation ID for an execute message
+    pub fn next_execution_id(&self) -> u64 {
+        self.next_execution_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Start collecting acks for an execute message, until the returned wait is dropped
+    pub fn expect_acks(&self, execution_id: u64) -> AckWait<'_> {
+        let (tx, acks) = mpsc::unbounded_channel();
+        self.pending_acks.lock().unwrap().insert(execution_id, tx);
+        AckWait {
+            client_manager: self,
+            execution_id,
+            acks,
+        }
+    }
+
+    /// Pass a client's ack on to the request waiting for it
+    /// Returns false if nothing is waiting, e.g. because the wait already timed out
+    pub fn record_ack(&self, client_id: usize, execution_id: u64) -> bool {
+        match self.pending_acks.lock().unwrap().get(&execution_id) {
+            Some(tx) => tx.send(client_id).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Get the IDs of all connected clients in ascending order
+    pub async fn client_ids(&self) -> Vec<usize> {
+        let mut ids: Vec<usize> = self.state.read().await.clients.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Get the ID of the client in a slot, if that many clients are connected
+    /// Slots count connected clients in connection order, so they close up when one leaves
+    pub async fn client_in_slot(&self, slot: usize) -> Option<usize> {
+        self.client_ids().await.get(slot).copied()
+    }
+
+    /// Get up to `count` clients that have gone the longest without receiving a broadcast
+    /// Clients that have never been targeted come first, ties are broken by ID
+    pub async fn least_recently_targeted(&self, count: usize) -> Vec<usize> {
+        let state = self.state.read().await;
+        let mut ids_by_age: Vec<(Option<Instant>, usize)> = state
+            .clients
+            .iter()
+            .map(|(id, entry)| (entry.last_targeted, *id))
+            .collect();
+        drop(state);
+        ids_by_age.sort_unstable();
+
+        ids_by_age
+            .into_iter()
+            .take(count)
+            .map(|(_, id)| id)
+            .collect()
+    }
+
+    /// Describe every connected client in ascending ID order
+    pub async fn list_clients(&self) -> Vec<ClientInfo> {
+        let state = self.state.read().await;
+        let now = Local::now();
+
+        let mut infos: Vec<ClientInfo> = state
+            .clients
+            .iter()
+            .map(|(id, entry)| {
+                let connected_for = entry.connected_at.elapsed();
+                ClientInfo {
+                    id: *id,
+                    slot: 0,
+                    connected_at: Timestamp::from(
+                        now - chrono::Duration::from_std(connected_for).unwrap_or_default(),
+                    ),
+                    uptime_secs: connected_for.as_secs(),
+                    last_pong_secs_ago: entry.last_pong.elapsed().as_secs(),
+                    rtt_ms: entry.rtt_ms,
+                    name: entry.name.clone(),
+                    tags: entry.tags.clone(),
+                    address: entry.address.map(|address| address.to_string()),
+                    client_uuid: entry.uuid.clone(),
+                    protocol: entry.protocol,
+                }
+            })
+            .collect();
+        drop(state);
+        // IDs are handed out in connection order, so slots follow them
+        infos.sort_unstable_by_key(|info| info.id);
+        for (slot, info) in infos.iter_mut().enumerate() {
+            info.slot = slot;
+        }
+        infos
+    }
+
+    /// Seconds since the client manager, and so the server, was created
+    pub fn uptime_secs(&self) -> u64 {
+        self.started_at.elapsed().as_secs()
+    }
+
+    /// Usage counters since startup
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
     }
 
     /// Get the current number of connected clients
     pub async fn client_count(&self) -> usize {
-        self.clients.lock().await.len()
+        self.state.read().await.clients.len()
+    }
+
+    /// Check whether the circuit breaker lets a broadcast through, returning the trial to hold
+    /// while sending when the broadcast is testing a half-open breaker
+    /// Returns the seconds to wait before retrying when it refuses
+    pub async fn check_circuit(&self) -> Result<Option<BreakerTrial<'_>>, u64> {
+        match &self.circuit_breaker {
+            Some(circuit_breaker) => circuit_breaker.check().await,
+            None => Ok(None),
+        }
+    }
+
+    /// Check whether a script repeats the last one sent within the debounce window
+    /// Always false when debouncing is disabled
+    pub async fn is_debounced(&self, msg_type: &str, filename: &str, script: &str) -> bool {
+        match &self.debouncer {
+            Some(debouncer) => debouncer.is_duplicate(msg_type, filename, script).await,
+            None => false,
+        }
+    }
+
+    /// Let a script that reached no one be sent again inside the debounce window
+    pub async fn forget_debounced(&self, msg_type: &str, filename: &str, script: &str) {
+        if let Some(debouncer) = &self.debouncer {
+            debouncer.forget(msg_type, filename, script).await;
+        }
+    }
+
+    /// Claim files for one execute request, so a concurrent request for any of them is refused
+    /// rather than broadcasting the same file twice
+    /// Fails with the first file already being executed
+    pub fn claim_files(&self, paths: &[String]) -> Result<InFlightGuard<'_>, String> {
+        self.in_flight.claim(paths)
+    }
+
+    /// Wait for a turn to read, serialize and broadcast a script under
+    /// `--max-concurrent-broadcasts`, returning at once when there is no limit
+    /// The turn lasts until the returned permit is dropped
+    pub async fn broadcast_permit(&self) -> Option<SemaphorePermit<'_>> {
+        match &self.broadcast_permits {
+            // The semaphore is never closed, so acquiring can't fail
+            Some(permits) => permits.acquire().await.ok(),
+            None => None,
+        }
+    }
+
+    /// Get the circuit breaker state, or None when no breaker is configured
+    pub async fn circuit_state(&self) -> Option<BreakerState> {
+        match &self.circuit_breaker {
+            Some(circuit_breaker) => Some(circuit_breaker.state().await),
+            None => None,
+        }
+    }
+
+    /// Whether new connections are currently being refused
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Toggle draining mode and return the new state
+    pub fn toggle_draining(&self) -> bool {
+        let draining = !self.draining.fetch_xor(true, Ordering::Relaxed);
+        if draining {
+            log("Draining: refusing new connections");
+        } else {
+            log("Drain cancelled: accepting new connections");
+        }
+        draining
     }
 
     /// Update the last pong time for a client
-    pub async fn update_pong(&self, id: usize) {
-        let mut last_pong = self.last_pong.lock().await;
-        last_pong.insert(id, Instant::now());
+    pub async fn update_pong(&self, id: usize, echoed_ts: Option<i64>) {
+        if let Some(entry) = self.state.write().await.clients.get_mut(&id) {
+            entry.last_pong = Instant::now();
+            // A timestamp from the future can't be one of ours, so it's ignored
+            if let Some(ts) = echoed_ts {
+                if let Ok(rtt_ms) = u64::try_from(Utc::now().timestamp_millis() - ts) {
+                    entry.rtt_ms = Some(rtt_ms);
+                }
+            }
+        }
+    }
+
+    /// Set the ping interval a client asked for, clamped to the allowed bounds
+    /// Returns the interval that will actually be used
+    pub async fn set_ping_interval(&self, id: usize, requested_secs: u64) -> u64 {
+        let interval = requested_secs.clamp(MIN_PING_INTERVAL_SECS, MAX_PING_INTERVAL_SECS);
+
+        if let Some(entry) = self.state.write().await.clients.get_mut(&id) {
+            entry.ping_interval = Some(interval);
+        }
+
+        log(&format!(
+            "Client {} ping interval set to {} seconds",
+            id, interval
+        ));
+        interval
+    }
+
+    /// Set the name a client registered in its hello, truncated to the allowed length
+    /// Returns false if the name is blank or the client is gone
+    pub async fn set_name(&self, id: usize, name: &str) -> bool {
+        let name: String = name.trim().chars().take(MAX_CLIENT_NAME_CHARS).collect();
+        if name.is_empty() {
+            return false;
+        }
+
+        match self.state.write().await.clients.get_mut(&id) {
+            Some(entry) => entry.name = Some(name.clone()),
+            None => return false,
+        }
+
+        log(&format!("Client {} registered as '{}'", id, name));
+        true
+    }
+
+    /// Give a client the stable identity `uuid`, restoring the name and tags it last had under
+    /// that UUID and closing any other connection that claimed it, so a reconnect replaces
+    /// the stale one. Returns false if the UUID is blank or the client isn't connected
+    pub async fn claim_uuid(&self, id: usize, uuid: &str) -> bool {
+        let uuid: String = uuid.trim().chars().take(MAX_CLIENT_UUID_CHARS).collect();
+        if uuid.is_empty() {
+            return false;
+        }
+
+        let mut state = self.state.write().await;
+        if !state.clients.contains_key(&id) {
+            return false;
+        }
+        let previous: Vec<usize> = state
+            .clients
+            .iter()
+            .filter(|(other, entry)| **other != id && entry.uuid.as_ref() == Some(&uuid))
+            .map(|(other, _)| *other)
+            .collect();
+        let replaced = state.take_clients(&previous, Some(REPLACED_CLOSE_REASON));
+
+        let profile = state.profiles.get(&uuid).cloned();
+        let Some(entry) = state.clients.get_mut(&id) else {
+            return false;
+        };
+        entry.uuid = Some(uuid.clone());
+        if let Some(profile) = profile {
+            entry.name = entry.name.take().or(profile.name);
+            if entry.tags.is_empty() {
+                entry.tags = profile.tags;
+            }
+        }
+        let label = entry.label(id);
+        let count = state.clients.len();
+        drop(state);
+
+        for previous in &replaced {
+            log(&format!(
+                "Client {} replaced client {} with the same UUID",
+                label, previous.label
+            ));
+        }
+        self.finish_removal(&replaced, Some(REPLACED_CLOSE_REASON), count)
+            .await;
+        log(&format!("Client {} identified as {}", label, uuid));
+        true
+    }
+
+    /// Log a script result reported by a client and forward it to the webhook, if configured
+    pub async fn report_result(&self, id: usize, result: ResultMessage) {
+        let (label, name) = match self.state.read().await.clients.get(&id) {
+            Some(entry) => (entry.label(id), entry.name.clone()),
+            None => (id.to_string(), None),
+        };
+        let filename = result.filename.as_deref().unwrap_or("unknown");
+        match &result.error {
+            Some(error) => warn(&format!(
+                "Client {} reported an error from '{}': {}",
+                label, filename, error
+            )),
+            None => log(&format!(
+                "Client {} reported a result from '{}': {}",
+                label,
+                filename,
+                result.output.as_deref().unwrap_or("")
+            )),
+        }
+
+        if let Some(webhook) = &self.result_webhook {
+            webhook.send(ResultReport {
+                client_id: id,
+                client_name: name,
+                filename: result.filename,
+                output: result.output,
+                error: result.error,
+                timestamp: Timestamp::now(),
+            });
+        }
+    }
+
+    /// Replace the tags a client registered, ignoring blanks and duplicates
+    pub async fn set_tags(&self, id: usize, tags: &[String]) {
+        let mut unique: Vec<String> = Vec::new();
+        for tag in tags.iter().map(|tag| tag.trim()) {
+            if !tag.is_empty() && !unique.iter().any(|existing| existing == tag) {
+                unique.push(tag.to_string());
+            }
+        }
+
+        let mut state = self.state.write().await;
+        let Some(entry) = state.clients.get_mut(&id) else {
+            return;
+        };
+        let label = entry.label(id);
+        entry.tags = unique.clone();
+        drop(state);
+
+        log(&format!("Client {} tagged {:?}", label, unique));
+    }
+
+    /// Record the protocol version a client reported in its hello
+    pub async fn set_protocol(&self, id: usize, protocol: u32) {
+        if let Some(entry) = self.state.write().await.clients.get_mut(&id) {
+            entry.protocol = Some(protocol);
+        }
+    }
+
+    /// Split clients into those speaking at least `min_protocol` and those that don't, both
+    /// sorted by ID
+    /// Looks at `ids` when given, skipping any that aren't connected, or at every client otherwise
+    pub async fn split_by_protocol(
+        &self,
+        ids: Option<&[usize]>,
+        min_protocol: u32,
+    ) -> (Vec<usize>, Vec<usize>) {
+        let state = self.state.read().await;
+        let protocol = |entry: &ClientEntry| entry.protocol.unwrap_or(BASE_PROTOCOL_VERSION);
+        let (mut eligible, mut skipped): (Vec<usize>, Vec<usize>) = match ids {
+            Some(ids) => ids
+                .iter()
+                .filter(|id| state.clients.contains_key(id))
+                .partition(|id| protocol(&state.clients[id]) >= min_protocol),
+            None => state
+                .clients
+                .keys()
+                .partition(|id| protocol(&state.clients[id]) >= min_protocol),
+        };
+        drop(state);
+        eligible.sort_unstable();
+        skipped.sort_unstable();
+        (eligible, skipped)
+    }
+
+    /// Count the clients that registered a tag
+    pub async fn count_by_tag(&self, tag: &str) -> usize {
+        let state = self.state.read().await;
+        state
+            .clients
+            .values()
+            .filter(|entry| entry.tags.iter().any(|t| t == tag))
+            .count()
+    }
+
+    /// Get the IDs of clients that registered a tag, sorted
+    pub async fn client_ids_with_tag(&self, tag: &str) -> Vec<usize> {
+        let state = self.state.read().await;
+        let mut ids: Vec<usize> = state
+            .clients
+            .iter()
+            .filter(|(_, entry)| entry.tags.iter().any(|t| t == tag))
+            .map(|(id, _)| *id)
+            .collect();
+        drop(state);
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Describe a client for log lines, by name if it registered one and by ID otherwise
+    pub async fn label(&self, id: usize) -> String {
+        match self.state.read().await.clients.get(&id) {
+            Some(entry) => entry.label(id),
+            None => id.to_string(),
+        }
+    }
+
+    /// Get the ping interval used for a client
+    pub async fn ping_interval(&self, id: usize) -> u64 {
+        self.state
+            .read()
+            .await
+            .clients
+            .get(&id)
+            .map_or(self.default_ping_interval, |entry| {
+                entry.ping_interval(self.default_ping_interval)
+            })
     }
 
     /// Send ping message to all clients
     pub async fn send_ping(&self) -> usize {
-        let senders = self.senders.lock().await;
-        let total = senders.len();
+        let ids = self.client_ids().await;
+        self.ping_clients(&ids).await
+    }
 
-        if total == 0 {
+    /// Send ping message to a single client
+    /// Returns false if the client isn't connected or the ping couldn't be sent
+    pub async fn send_ping_to(&self, id: usize) -> bool {
+        self.ping_clients(&[id]).await > 0
+    }
+
+    /// Send ping message to clients whose own ping interval has elapsed
+    pub async fn send_due_pings(&self) -> usize {
+        let state = self.state.read().await;
+        let now = Instant::now();
+
+        let due: Vec<usize> = state
+            .clients
+            .iter()
+            .filter(|(_, entry)| {
+                now.duration_since(entry.last_ping).as_secs()
+                    >= entry.ping_interval(self.default_ping_interval)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        drop(state);
+
+        if due.is_empty() {
             return 0;
         }
+        self.ping_clients(&due).await
+    }
+
+    /// Send ping message to the given clients and record when they were pinged
+    async fn ping_clients(&self, ids: &[usize]) -> usize {
+        let ping = PingMessage {
+            msg_type: "ping".to_string(),
+            ts: Some(Utc::now().timestamp_millis()),
+        };
+        let Ok(ping_message) = serde_json::to_string(&ping) else {
+            return 0;
+        };
 
-        let ping_message = r#"{"type":"ping"}"#;
+        let mut state = self.state.write().await;
+        let now = Instant::now();
         let mut successful = 0;
+        let mut total = 0;
 
-        for (id, sender) in senders.iter() {
-            if sender.send(Message::text(ping_message)).is_ok() {
+        for id in ids {
+            let Some(entry) = state.clients.get_mut(id) else {
+                continue;
+            };
+            total += 1;
+            entry.last_ping = now;
+            if entry
+                .sender
+                .try_send(Message::text(ping_message.clone()))
+                .is_ok()
+            {
                 successful += 1;
             } else {
-                log(&format!("Failed to send ping to client {}", id));
+                warn(&format!("Failed to send ping to client {}", id));
             }
         }
+        drop(state);
+
+        if total == 0 {
+            return 0;
+        }
 
         log(&format!("Sent ping to {}/{} clients", successful, total));
         successful
     }
 
     /// Check for clients that haven't responded to pings within the timeout
+    /// The timeout is scaled for clients that negotiated a non-default ping interval
     /// Returns a list of timed-out client IDs
     pub async fn check_timeouts(&self, timeout_secs: u64) -> Vec<usize> {
-        let last_pong = self.last_pong.lock().await;
+        let state = self.state.read().await;
         let now = Instant::now();
-        let mut timed_out = Vec::new();
-
-        for (id, last_time) in last_pong.iter() {
-            if now.duration_since(*last_time).as_secs() > timeout_secs {
-                timed_out.push(*id);
-            }
-        }
 
-        timed_out
+        state
+            .clients
+            .iter()
+            .filter(|(_, entry)| {
+                let client_timeout_secs = timeout_secs
+                    * entry.ping_interval(self.default_ping_interval)
+                    / self.default_ping_interval;
+                now.duration_since(entry.last_pong).as_secs() > client_timeout_secs
+            })
+            .map(|(id, _)| *id)
+            .collect()
     }
 
     /// Disconnect clients by their IDs
+    /// Clients that have already disconnected are skipped
     pub async fn disconnect_clients(&self, client_ids: Vec<usize>) {
         if client_ids.is_empty() {
             return;
         }
 
-        let mut clients = self.clients.lock().await;
-        let mut senders = self.senders.lock().await;
-        let mut last_pong = self.last_pong.lock().await;
-
-        for id in client_ids {
-            clients.remove(&id);
-            senders.remove(&id);
-            last_pong.remove(&id);
-            log(&format!("Client {} timed out and was disconnected", id));
+        let (removed, count) = self
+            .remove_clients(&client_ids, Some(PING_TIMEOUT_CLOSE_REASON))
+            .await;
+        if removed.is_empty() {
+            return;
         }
 
-        let count = clients.len();
+        for client in removed {
+            warn(&format!(
+                "Client {} timed out and was disconnected after {}s",
+                client.label, client.uptime_secs
+            ));
+            self.publish(
+                CLIENT_TIMEOUT_EVENT,
+                serde_json::json!({ "client_id": client.id, "uptime_secs": client.uptime_secs }),
+            );
+        }
         log(&format!("Remaining clients: {}", count));
     }
+
+    /// Kick a single client, sending it a close frame before dropping its sender
+    /// Returns false if the client isn't connected
+    pub async fn disconnect(&self, id: usize) -> bool {
+        let (removed, count) = self.remove_clients(&[id], Some(KICKED_CLOSE_REASON)).await;
+        let Some(client) = removed.first() else {
+            return false;
+        };
+
+        log(&format!(
+            "Client {} was kicked after {}s. Total clients: {}",
+            client.label, client.uptime_secs, count
+        ));
+        true
+    }
 }
 
 impl Default for ClientManager {
@@ -182,8 +1488,111 @@ impl Default for ClientManager {
     }
 }
 
+/// Send frames to one client in order, without waiting for room in its buffer
+/// A message that doesn't fit is not started, so clients never get part of a chunked script
+fn send_all(sender: &Sender<Message>, frames: &[Message]) -> Result<(), SendFailure> {
+    if sender.is_closed() {
+        return Err(SendFailure::Closed);
+    }
+    if sender.capacity() < frames.len() {
+        return Err(SendFailure::Full);
+    }
+    for frame in frames {
+        sender.try_send(frame.clone()).map_err(|e| match e {
+            TrySendError::Full(_) => SendFailure::Full,
+            TrySendError::Closed(_) => SendFailure::Closed,
+        })?;
+    }
+    Ok(())
+}
+
+/// Spawn a hook command without waiting for it, appending the client ID and name, if given, as
+/// arguments
+fn run_hook(command: &str, id: usize, name: Option<&str>) {
+    let Some(parts) = split_command(command) else {
+        warn(&format!("Hook '{}' has an unclosed quote", command));
+        return;
+    };
+    let Some((program, args)) = parts.split_first() else {
+        return;
+    };
+
+    let result = Command::new(program)
+        .args(args)
+        .arg(id.to_string())
+        .args(name)
+        .stdin(Stdio::null())
+        .spawn();
+
+    if let Err(e) = result {
+        warn(&format!("Failed to run hook '{}': {}", command, e));
+    }
+}
+
+/// When set, informational log lines are suppressed and only warnings are printed
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable quiet logging
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// When set, log lines are written as JSON objects instead of plain text
+static JSON_LOGS: AtomicBool = AtomicBool::new(false);
+
+/// Choose how log lines are formatted
+pub fn set_log_format(format: LogFormat) {
+    JSON_LOGS.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+/// File log lines are also appended to, set with `--log-file`
+static LOG_FILE: std::sync::Mutex<Option<LogFile>> = std::sync::Mutex::new(None);
+
+/// Append every log line to this file as well as printing it
+pub fn set_log_file(file: LogFile) {
+    *LOG_FILE.lock().unwrap() = Some(file);
+}
+
 /// Log a message with timestamp
+/// Quiet mode keeps it off stdout, but it still reaches the log file
 pub fn log(message: &str) {
-    let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S");
-    println!("[{}] {}", timestamp, message);
+    emit("info", message);
+}
+
+/// Log a warning with timestamp, printed even in quiet mode
+pub fn warn(message: &str) {
+    emit("warn", message);
+}
+
+/// Print a log line in the configured format and append it to the log file, if any
+fn emit(level: &str, message: &str) {
+    let now = Local::now();
+    // Text lines keep a short local time unless an epoch format was asked for
+    let timestamp = Timestamp::from(now);
+    let stamp = match &timestamp {
+        Timestamp::Rfc3339(_) => now.format("%Y-%m-%d %H:%M:%S").to_string(),
+        Timestamp::Epoch(epoch) => epoch.to_string(),
+    };
+    let line = if JSON_LOGS.load(Ordering::Relaxed) {
+        serde_json::json!({
+            "timestamp": timestamp,
+            "level": level,
+            "message": message,
+        })
+        .to_string()
+    } else if level == "info" {
+        format!("[{}] {}", stamp, message)
+    } else {
+        format!("[{}] [{}] {}", stamp, level.to_uppercase(), message)
+    };
+
+    if level != "info" || !QUIET.load(Ordering::Relaxed) {
+        println!("{}", line);
+    }
+    if let Some(file) = LOG_FILE.lock().unwrap().as_mut() {
+        // Reporting the failure through the log would only fail again
+        if let Err(e) = file.write_line(&line) {
+            eprintln!("Failed to write to log file: {}", e);
+        }
+    }
 }
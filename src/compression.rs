@@ -0,0 +1,50 @@
+use warp::filters::BoxedFilter;
+use warp::{Filter, Rejection, Reply};
+
+use crate::client_ip::optional_header;
+
+/// Whether an `Accept-Encoding` header value allows a gzip response
+pub fn accepts_gzip(accept_encoding: &str) -> bool {
+    accept_encoding.split(',').any(|coding| {
+        let mut params = coding.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let refused = params.any(|param| {
+            param
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+    })
+}
+
+/// Gzip the replies of `routes` for requests that accept it, sending the rest uncompressed
+pub fn gzip_if_accepted<F, R>(routes: F) -> BoxedFilter<(Box<dyn Reply>,)>
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply + 'static,
+{
+    let wants_gzip = optional_header("accept-encoding")
+        .and_then(|accept_encoding: Option<String>| async move {
+            if accept_encoding.is_some_and(|value| accepts_gzip(&value)) {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one();
+
+    let compressed = wants_gzip
+        .and(routes.clone())
+        .with(warp::compression::gzip())
+        .map(|reply| Box::new(reply) as Box<dyn Reply>);
+    let plain = routes.map(|reply| Box::new(reply) as Box<dyn Reply>);
+
+    // Caches must not serve a compressed reply to a client that didn't ask for one
+    compressed
+        .or(plain)
+        .unify()
+        .with(warp::reply::with::header("vary", "accept-encoding"))
+        .map(|reply| Box::new(reply) as Box<dyn Reply>)
+        .boxed()
+}
@@ -1,44 +1,502 @@
 use std::env;
+use std::fs;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::types::WelcomeMessage;
 
 // Default server settings
 const DEFAULT_HTTP_PORT: u16 = 13377;
 const DEFAULT_WS_PORT: u16 = 13378;
 const DEFAULT_HOST: &str = "localhost";
+const DEFAULT_EXECUTE_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_BREAKER_COOLDOWN_SECS: u64 = 30;
+const DEFAULT_ACK_TIMEOUT_SECS: u64 = 5;
+const DEFAULT_QUEUE_TTL_SECS: u64 = 60;
+const DEFAULT_MAX_SCRIPT_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_LOG_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Version of the message protocol, sent to clients in the welcome message
+/// Bumped whenever a change would break clients written for an older version
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Protocol version assumed for clients that don't report one in their hello
+pub const BASE_PROTOCOL_VERSION: u32 = 1;
+
+/// File extensions allowed for script execution unless overridden with `--allowed-ext`
+pub const DEFAULT_ALLOWED_EXTENSIONS: &[&str] = &[".lua", ".luau", ".txt"];
+
+/// Extension of structured execute payloads, allowed only when JSON payloads are enabled
+pub const JSON_PAYLOAD_EXTENSION: &str = ".json";
+
+/// Extensions of precompiled bytecode, sent base64-encoded as `execute_binary` messages when
+/// they are also allowed with `--allowed-ext`
+pub const BINARY_EXTENSIONS: &[&str] = &[".luac", ".bin"];
 
-/// Allowed file extensions for script execution
-pub const ALLOWED_EXTENSIONS: &[&str] = &[".lua", ".luau", ".txt"];
+/// Message type used for bytecode scripts
+pub const BINARY_MESSAGE_TYPE: &str = "execute_binary";
 
-/// Interval between ping messages sent to clients
+/// Message type of each piece of a script split up by `--chunk-size`
+pub const CHUNK_MESSAGE_TYPE: &str = "execute_chunk";
+
+/// How long before the execute timeout a wait for acks gives up, leaving time to reply with
+/// the acks collected so far
+pub const EXECUTE_RESPONSE_MARGIN_MS: u64 = 250;
+
+/// Message types an execute request may use; the first entry is the default
+pub const EXECUTE_MESSAGE_TYPES: &[&str] = &["execute", "run", "loadstring"];
+
+/// Default interval between ping messages sent to clients
 pub const PING_INTERVAL_SECS: u64 = 30;
 
-/// Timeout duration - clients that don't respond within this time are disconnected
+/// Bounds for ping intervals negotiated by individual clients
+pub const MIN_PING_INTERVAL_SECS: u64 = 5;
+pub const MAX_PING_INTERVAL_SECS: u64 = 300;
+
+/// How often the ping task checks which clients are due a ping
+pub const PING_SCHEDULER_TICK_SECS: u64 = 1;
+
+/// Default timeout - clients that don't respond within this time are disconnected
 pub const PONG_TIMEOUT_SECS: u64 = 90;
 
+/// Path the WebSocket endpoint is mounted on when both servers share the HTTP port
+pub const SINGLE_PORT_WS_PATH: &str = "ws";
+
+/// Longest name a client may register in its hello; longer names are truncated
+pub const MAX_CLIENT_NAME_CHARS: usize = 64;
+
+/// Longest client UUID kept from a hello; longer ones are truncated
+pub const MAX_CLIENT_UUID_CHARS: usize = 64;
+
+/// Most disconnected client UUIDs whose name and tags are remembered for their next connection
+pub const MAX_REMEMBERED_UUIDS: usize = 1024;
+
+/// Number of recent broadcasts kept for /history unless `--history-size` says otherwise
+pub const DEFAULT_HISTORY_SIZE: usize = 50;
+
+/// Oldest script `--replay-last` sends to newly connected clients
+pub const REPLAY_MAX_AGE_SECS: u64 = 600;
+
+/// Default number of messages each client's channel holds before it counts as a slow consumer
+pub const DEFAULT_CLIENT_BUFFER: usize = 1024;
+
+/// Server events held for each /events subscriber before a slow one starts missing events
+pub const EVENT_BUFFER: usize = 256;
+
+/// Characters of each script kept in /history entries
+pub const HISTORY_PREVIEW_CHARS: usize = 200;
+
+/// How long to wait before retrying a failed send with `--retry-sends`
+pub const SEND_RETRY_DELAY_MS: u64 = 100;
+
+/// Maximum number of scripts held for the next client when queueing on empty
+pub const QUEUE_CAPACITY: usize = 10;
+
+/// How long shutdown waits for notified clients to close their connections
+pub const SHUTDOWN_GRACE_SECS: u64 = 2;
+
+/// Background tasks that haven't ticked within this time are reported as unhealthy
+/// The ping scheduler ticks every `PING_SCHEDULER_TICK_SECS` and the timeout checker every
+/// `MIN_PING_INTERVAL_SECS`, so a few of the slower period leaves room for a late tick
+pub const BACKGROUND_TASK_STALE_SECS: u64 = MIN_PING_INTERVAL_SECS * 3;
+
+/// Prefix of the environment variables that can stand in for each flag
+pub const ENV_PREFIX: &str = "RBXPROXY_";
+
+/// Flags that can be set through environment variables, and whether each takes a value
+/// Switches without a value are turned on by `1`, `true` or `yes`
+const ENV_FLAGS: &[(&str, bool)] = &[
+    ("--http-port", true),
+    ("--ws-port", true),
+    ("--host", true),
+    ("--http-host", true),
+    ("--ws-host", true),
+    ("--execute-timeout-secs", true),
+    ("--pid-file", true),
+    ("--quiet", false),
+    ("--log-format", true),
+    ("--timestamp-format", true),
+    ("--log-file", true),
+    ("--log-max-bytes", true),
+    ("--on-connect", true),
+    ("--on-disconnect", true),
+    ("--on-empty", true),
+    ("--json-payloads", false),
+    ("--trust-forwarded", false),
+    ("--trusted-proxy", true),
+    ("--allowed-ext", true),
+    ("--breaker-threshold", true),
+    ("--breaker-cooldown-secs", true),
+    ("--ack-timeout-secs", true),
+    ("--api-key", true),
+    ("--queue-on-empty", false),
+    ("--queue-ttl-secs", true),
+    ("--single-port", false),
+    ("--broadcast-count", false),
+    ("--retry-sends", false),
+    ("--drop-slow-clients", false),
+    ("--replay-last", false),
+    ("--compress", false),
+    ("--minify", false),
+    ("--compress-responses", false),
+    ("--max-script-bytes", true),
+    ("--cors-origin", true),
+    ("--watch", true),
+    ("--script-root", true),
+    ("--library", true),
+    ("--base-path", true),
+    ("--tls-cert", true),
+    ("--tls-key", true),
+    ("--ping-interval", true),
+    ("--pong-timeout", true),
+    ("--rate-limit", true),
+    ("--max-clients", true),
+    ("--max-concurrent-broadcasts", true),
+    ("--chunk-size", true),
+    ("--client-buffer", true),
+    ("--history-size", true),
+    ("--debounce-ms", true),
+    ("--result-webhook", true),
+];
+
+/// Environment variable for a flag: the prefix plus the flag in upper snake case, so
+/// `--http-port` becomes `RBXPROXY_HTTP_PORT`
+pub fn env_var_name(flag: &str) -> String {
+    format!(
+        "{}{}",
+        ENV_PREFIX,
+        flag.trim_start_matches("--")
+            .replace('-', "_")
+            .to_uppercase()
+    )
+}
+
+/// How log lines are written to stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `[timestamp] message`, for reading in a terminal
+    #[default]
+    Text,
+    /// One JSON object per line with `timestamp`, `level` and `message`
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => anyhow::bail!("Invalid log format '{}', expected 'text' or 'json'", s),
+        }
+    }
+}
+
+/// What to do once the last client disconnects
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnEmpty {
+    /// Nothing beyond the usual disconnect log line
+    #[default]
+    None,
+    /// Log a warning, shown even in quiet mode
+    Log,
+    /// Shut down gracefully, as on Ctrl+C
+    Exit,
+}
+
+impl FromStr for OnEmpty {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Self::None),
+            "log" => Ok(Self::Log),
+            "exit" => Ok(Self::Exit),
+            _ => anyhow::bail!(
+                "Invalid on-empty action '{}', expected 'exit', 'log' or 'none'",
+                s
+            ),
+        }
+    }
+}
+
+/// How timestamps are written in messages, responses and log lines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampFormat {
+    /// RFC 3339 strings like `2025-10-28T12:34:56.789+00:00`
+    #[default]
+    Rfc3339,
+    /// Unix epoch milliseconds, as a number
+    EpochMs,
+    /// Unix epoch seconds, as a number
+    EpochS,
+}
+
+impl FromStr for TimestampFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "rfc3339" => Ok(Self::Rfc3339),
+            "epoch_ms" => Ok(Self::EpochMs),
+            "epoch_s" => Ok(Self::EpochS),
+            _ => anyhow::bail!(
+                "Invalid timestamp format '{}', expected 'rfc3339', 'epoch_ms' or 'epoch_s'",
+                s
+            ),
+        }
+    }
+}
+
 /// Server configuration
+///
+/// Settings are resolved in order of precedence: command-line flags, then environment variables,
+/// then the TOML file given with `--config` (if any), then the built-in defaults. File keys use
+/// the field names below.
+///
+/// Every flag has an environment variable named `RBXPROXY_` plus the flag in upper snake case:
+/// `RBXPROXY_HTTP_PORT`, `RBXPROXY_HOST`, `RBXPROXY_API_KEY`, `RBXPROXY_CONFIG` and so on.
+/// Variables take the same values as their flags, lists are comma-separated, and switches like
+/// `RBXPROXY_QUIET` are turned on with `1`, `true` or `yes`.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ServerConfig {
     pub http_host: String,
     pub http_port: u16,
     pub ws_host: String,
     pub ws_port: u16,
+    /// Upper bound on how long a single execute request may take
+    pub execute_timeout_secs: u64,
+    /// File to write the process ID to on startup
+    pub pid_file: Option<PathBuf>,
+    /// Suppress the startup banner and informational logging
+    pub quiet: bool,
+    /// Plain text or JSON log lines
+    pub log_format: LogFormat,
+    pub timestamp_format: TimestampFormat,
+    /// File every log line is also appended to, whether or not `quiet` is set
+    pub log_file: Option<PathBuf>,
+    /// Size the log file may reach before it is rotated
+    pub log_max_bytes: u64,
+    /// Command spawned with the client ID whenever a client connects
+    pub on_connect: Option<String>,
+    /// Command spawned with the client ID, and name if it has one, whenever a client disconnects
+    pub on_disconnect: Option<String>,
+    /// Action taken when the last client disconnects
+    pub on_empty: OnEmpty,
+    /// Treat .json files as structured execute payloads instead of raw scripts
+    pub json_payloads: bool,
+    /// Take the caller IP from X-Forwarded-For instead of the socket address
+    pub trust_forwarded: bool,
+    /// Proxies whose forwarded headers are trusted; loopback only when empty
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Failure rate (0-1) over recent broadcasts that opens the circuit breaker; disabled when unset
+    pub breaker_threshold: Option<f64>,
+    /// How long the circuit breaker stays open before letting a trial broadcast through
+    pub breaker_cooldown_secs: u64,
+    /// How long an execute request waits for client acks when asked to
+    pub ack_timeout_secs: u64,
+    /// Key required on the execute endpoints; open to anyone when unset
+    pub api_key: Option<String>,
+    /// Hold scripts sent while no clients are connected and deliver them to the next client
+    pub queue_on_empty: bool,
+    /// How long a queued script stays deliverable
+    pub queue_ttl_secs: u64,
+    /// Send large messages as gzip-compressed binary frames instead of JSON text
+    pub compress: bool,
+    /// Strip comments and indentation from Lua scripts before sending them
+    pub minify: bool,
+    /// Gzip the informational GET responses for clients that accept it
+    pub compress_responses: bool,
+    /// Largest script file or inline body accepted for execution
+    pub max_script_bytes: u64,
+    /// File extensions accepted by /execute_file, lowercase with a leading dot
+    pub allowed_extensions: Vec<String>,
+    /// URL that script results reported by clients are POSTed to; only logged when unset
+    pub result_webhook: Option<String>,
+    /// Origins allowed to call the HTTP endpoints from a browser, `*` for any; no CORS when empty
+    pub cors_origins: Vec<String>,
+    /// PEM certificate for serving HTTPS and WSS; must be set together with `tls_key`
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key matching `tls_cert`
+    pub tls_key: Option<PathBuf>,
+    /// Skip scripts identical to the last one sent within this many milliseconds; off when unset
+    pub debounce_ms: Option<u64>,
+    /// Seconds between pings for clients that don't negotiate their own interval
+    pub ping_interval_secs: u64,
+    /// Seconds without a pong before a client on the default ping interval is disconnected
+    pub pong_timeout_secs: u64,
+    /// Execute requests allowed per caller IP per minute; unlimited when unset
+    pub rate_limit: Option<u32>,
+    /// File or directory whose allowed scripts are broadcast whenever they change
+    pub watch: Option<PathBuf>,
+    /// Directory /execute_file paths must resolve inside, following `..` and symlinks;
+    /// any path is accepted when unset
+    pub script_root: Option<PathBuf>,
+    /// Directory of named scripts sent with /run/{name}; /run is unavailable when unset
+    pub library: Option<PathBuf>,
+    /// Serve WebSockets on `/ws` of the HTTP port instead of on a port of their own
+    pub single_port: bool,
+    /// Send clients a `client_count` message whenever the number connected changes
+    pub broadcast_count: bool,
+    /// Number of recent broadcasts kept for /history; 0 keeps none
+    pub history_size: usize,
+    /// Retry failed sends once after a short delay before dropping the client
+    pub retry_sends: bool,
+    /// Most clients connected at once; further connections are refused, unlimited when unset
+    pub max_clients: Option<usize>,
+    /// Most execute requests reading, serializing and broadcasting at once; the rest wait their
+    /// turn, unlimited when unset
+    pub max_concurrent_broadcasts: Option<usize>,
+    /// Largest message sent in one frame; bigger ones are split into chunks, unlimited when unset
+    pub chunk_size: Option<usize>,
+    /// Messages each client's channel holds before it counts as a slow consumer
+    pub client_buffer: usize,
+    /// Disconnect slow consumers instead of only skipping them
+    pub drop_slow_clients: bool,
+    /// Send newly connected clients the last broadcast script if it's recent
+    pub replay_last: bool,
+    /// Prefix every HTTP and WebSocket route is mounted under, like `/rbxproxy`; empty for the
+    /// root
+    pub base_path: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            http_host: DEFAULT_HOST.to_string(),
+            http_port: DEFAULT_HTTP_PORT,
+            ws_host: DEFAULT_HOST.to_string(),
+            ws_port: DEFAULT_WS_PORT,
+            execute_timeout_secs: DEFAULT_EXECUTE_TIMEOUT_SECS,
+            pid_file: None,
+            quiet: false,
+            log_format: LogFormat::default(),
+            timestamp_format: TimestampFormat::default(),
+            log_file: None,
+            log_max_bytes: DEFAULT_LOG_MAX_BYTES,
+            on_connect: None,
+            on_disconnect: None,
+            on_empty: OnEmpty::default(),
+            json_payloads: false,
+            trust_forwarded: false,
+            trusted_proxies: Vec::new(),
+            breaker_threshold: None,
+            breaker_cooldown_secs: DEFAULT_BREAKER_COOLDOWN_SECS,
+            ack_timeout_secs: DEFAULT_ACK_TIMEOUT_SECS,
+            api_key: None,
+            queue_on_empty: false,
+            queue_ttl_secs: DEFAULT_QUEUE_TTL_SECS,
+            compress: false,
+            minify: false,
+            compress_responses: false,
+            max_script_bytes: DEFAULT_MAX_SCRIPT_BYTES,
+            allowed_extensions: DEFAULT_ALLOWED_EXTENSIONS
+                .iter()
+                .map(|ext| ext.to_string())
+                .collect(),
+            result_webhook: None,
+            cors_origins: Vec::new(),
+            tls_cert: None,
+            tls_key: None,
+            debounce_ms: None,
+            ping_interval_secs: PING_INTERVAL_SECS,
+            pong_timeout_secs: PONG_TIMEOUT_SECS,
+            rate_limit: None,
+            watch: None,
+            script_root: None,
+            library: None,
+            single_port: false,
+            broadcast_count: false,
+            history_size: DEFAULT_HISTORY_SIZE,
+            retry_sends: false,
+            max_clients: None,
+            max_concurrent_broadcasts: None,
+            chunk_size: None,
+            client_buffer: DEFAULT_CLIENT_BUFFER,
+            drop_slow_clients: false,
+            replay_last: false,
+            base_path: String::new(),
+        }
+    }
 }
 
 impl ServerConfig {
-    /// Parse configuration from command-line arguments
+    /// Parse configuration from command-line arguments, layered over `RBXPROXY_` environment
+    /// variables and the `--config` file if given
     pub fn from_args() -> Result<Self> {
         let args: Vec<String> = env::args().collect();
-        let mut http_host = DEFAULT_HOST.to_string();
-        let mut http_port = DEFAULT_HTTP_PORT;
-        let mut ws_host = DEFAULT_HOST.to_string();
-        let mut ws_port = DEFAULT_WS_PORT;
+        Self::parse(&args, |name| env::var(name).ok())
+    }
+
+    /// Parse configuration from `args` (starting with the program name), falling back to the
+    /// variables `env` looks up, then the config file, then the defaults
+    pub fn parse(args: &[String], env: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let config_path = match args.iter().position(|arg| arg == "--config") {
+            Some(index) => Some(
+                args.get(index + 1)
+                    .context("--config requires a value")?
+                    .clone(),
+            ),
+            None => env(&env_var_name("--config")),
+        };
+        let mut config = match config_path {
+            Some(path) => Self::from_file(Path::new(&path))?,
+            None => Self::default(),
+        };
+
+        // Environment variables are applied as if they were flags given before the real ones,
+        // in a separate pass so a flag's first use still replaces lists set by a variable
+        let mut env_args = vec![args.first().cloned().unwrap_or_default()];
+        for (flag, takes_value) in ENV_FLAGS {
+            let name = env_var_name(flag);
+            let Some(value) = env(&name) else {
+                continue;
+            };
+            if *takes_value {
+                env_args.push(flag.to_string());
+                env_args.push(value);
+            } else {
+                match value.trim().to_lowercase().as_str() {
+                    "1" | "true" | "yes" => env_args.push(flag.to_string()),
+                    "" | "0" | "false" | "no" => {}
+                    _ => anyhow::bail!("{} must be true or false, got '{}'", name, value),
+                }
+            }
+        }
+        config
+            .apply_args(&env_args)
+            .context("Invalid RBXPROXY_ environment variable")?;
+        config.apply_args(args)?;
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Apply command-line style flags over the current values
+    fn apply_args(&mut self, args: &[String]) -> Result<()> {
+        let config = self;
+        let mut trusted_proxies_from_args = false;
+        let mut allowed_extensions_from_args = false;
+        let mut cors_origins_from_args = false;
+        // Applied after the loop so they win over --host wherever they appear
+        let mut http_host = None;
+        let mut ws_host = None;
 
         let mut i = 1;
         while i < args.len() {
             match args[i].as_str() {
                 "--http-port" => {
                     if i + 1 < args.len() {
-                        http_port = args[i + 1].parse().context("Invalid HTTP port")?;
+                        config.http_port = args[i + 1].parse().context("Invalid HTTP port")?;
                         i += 2;
                     } else {
                         anyhow::bail!("--http-port requires a value");
@@ -46,7 +504,7 @@ impl ServerConfig {
                 }
                 "--ws-port" => {
                     if i + 1 < args.len() {
-                        ws_port = args[i + 1].parse().context("Invalid WebSocket port")?;
+                        config.ws_port = args[i + 1].parse().context("Invalid WebSocket port")?;
                         i += 2;
                     } else {
                         anyhow::bail!("--ws-port requires a value");
@@ -54,16 +512,401 @@ impl ServerConfig {
                 }
                 "--host" => {
                     if i + 1 < args.len() {
-                        http_host = args[i + 1].clone();
-                        ws_host = args[i + 1].clone();
+                        config.http_host = args[i + 1].clone();
+                        config.ws_host = args[i + 1].clone();
                         i += 2;
                     } else {
                         anyhow::bail!("--host requires a value");
                     }
                 }
+                "--http-host" => {
+                    if i + 1 < args.len() {
+                        http_host = Some(args[i + 1].clone());
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--http-host requires a value");
+                    }
+                }
+                "--ws-host" => {
+                    if i + 1 < args.len() {
+                        ws_host = Some(args[i + 1].clone());
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--ws-host requires a value");
+                    }
+                }
+                "--execute-timeout-secs" => {
+                    if i + 1 < args.len() {
+                        config.execute_timeout_secs =
+                            args[i + 1].parse().context("Invalid execute timeout")?;
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--execute-timeout-secs requires a value");
+                    }
+                }
+                "--pid-file" => {
+                    if i + 1 < args.len() {
+                        config.pid_file = Some(PathBuf::from(&args[i + 1]));
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--pid-file requires a value");
+                    }
+                }
+                "--quiet" => {
+                    config.quiet = true;
+                    i += 1;
+                }
+                "--log-format" => {
+                    if i + 1 < args.len() {
+                        config.log_format = args[i + 1].parse()?;
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--log-format requires a value");
+                    }
+                }
+                "--timestamp-format" => {
+                    if i + 1 < args.len() {
+                        config.timestamp_format = args[i + 1].parse()?;
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--timestamp-format requires a value");
+                    }
+                }
+                "--log-file" => {
+                    if i + 1 < args.len() {
+                        config.log_file = Some(PathBuf::from(&args[i + 1]));
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--log-file requires a value");
+                    }
+                }
+                "--log-max-bytes" => {
+                    if i + 1 < args.len() {
+                        config.log_max_bytes =
+                            args[i + 1].parse().context("Invalid log file size limit")?;
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--log-max-bytes requires a value");
+                    }
+                }
+                "--on-connect" => {
+                    if i + 1 < args.len() {
+                        config.on_connect = Some(args[i + 1].clone());
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--on-connect requires a value");
+                    }
+                }
+                "--on-empty" => {
+                    if i + 1 < args.len() {
+                        config.on_empty = args[i + 1].parse()?;
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--on-empty requires a value");
+                    }
+                }
+                "--on-disconnect" => {
+                    if i + 1 < args.len() {
+                        config.on_disconnect = Some(args[i + 1].clone());
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--on-disconnect requires a value");
+                    }
+                }
+                "--json-payloads" => {
+                    config.json_payloads = true;
+                    i += 1;
+                }
+                "--trust-forwarded" => {
+                    config.trust_forwarded = true;
+                    i += 1;
+                }
+                "--trusted-proxy" => {
+                    if i + 1 < args.len() {
+                        // Proxies given on the command line replace those from the config file
+                        if !trusted_proxies_from_args {
+                            config.trusted_proxies.clear();
+                            trusted_proxies_from_args = true;
+                        }
+                        for proxy in args[i + 1].split(',') {
+                            let ip = proxy
+                                .trim()
+                                .parse()
+                                .with_context(|| format!("Invalid trusted proxy '{}'", proxy))?;
+                            config.trusted_proxies.push(ip);
+                        }
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--trusted-proxy requires a value");
+                    }
+                }
+                "--allowed-ext" => {
+                    if i + 1 < args.len() {
+                        // Extensions given on the command line replace the defaults and the file's
+                        if !allowed_extensions_from_args {
+                            config.allowed_extensions.clear();
+                            allowed_extensions_from_args = true;
+                        }
+                        config
+                            .allowed_extensions
+                            .extend(args[i + 1].split(',').map(normalize_extension));
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--allowed-ext requires a value");
+                    }
+                }
+                "--breaker-threshold" => {
+                    if i + 1 < args.len() {
+                        config.breaker_threshold =
+                            Some(args[i + 1].parse().context("Invalid breaker threshold")?);
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--breaker-threshold requires a value");
+                    }
+                }
+                "--breaker-cooldown-secs" => {
+                    if i + 1 < args.len() {
+                        config.breaker_cooldown_secs =
+                            args[i + 1].parse().context("Invalid breaker cooldown")?;
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--breaker-cooldown-secs requires a value");
+                    }
+                }
+                "--ack-timeout-secs" => {
+                    if i + 1 < args.len() {
+                        config.ack_timeout_secs =
+                            args[i + 1].parse().context("Invalid ack timeout")?;
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--ack-timeout-secs requires a value");
+                    }
+                }
+                "--api-key" => {
+                    if i + 1 < args.len() {
+                        config.api_key = Some(args[i + 1].trim().to_string());
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--api-key requires a value");
+                    }
+                }
+                "--queue-on-empty" => {
+                    config.queue_on_empty = true;
+                    i += 1;
+                }
+                "--queue-ttl-secs" => {
+                    if i + 1 < args.len() {
+                        config.queue_ttl_secs = args[i + 1].parse().context("Invalid queue TTL")?;
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--queue-ttl-secs requires a value");
+                    }
+                }
+                "--single-port" => {
+                    config.single_port = true;
+                    i += 1;
+                }
+                "--broadcast-count" => {
+                    config.broadcast_count = true;
+                    i += 1;
+                }
+                "--replay-last" => {
+                    config.replay_last = true;
+                    i += 1;
+                }
+                "--drop-slow-clients" => {
+                    config.drop_slow_clients = true;
+                    i += 1;
+                }
+                "--retry-sends" => {
+                    config.retry_sends = true;
+                    i += 1;
+                }
+                "--compress-responses" => {
+                    config.compress_responses = true;
+                    i += 1;
+                }
+                "--compress" => {
+                    config.compress = true;
+                    i += 1;
+                }
+                "--minify" => {
+                    config.minify = true;
+                    i += 1;
+                }
+                "--max-script-bytes" => {
+                    if i + 1 < args.len() {
+                        config.max_script_bytes =
+                            args[i + 1].parse().context("Invalid max script size")?;
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--max-script-bytes requires a value");
+                    }
+                }
+                "--cors-origin" => {
+                    if i + 1 < args.len() {
+                        // Origins given on the command line replace those from the config file
+                        if !cors_origins_from_args {
+                            config.cors_origins.clear();
+                            cors_origins_from_args = true;
+                        }
+                        config.cors_origins.extend(
+                            args[i + 1]
+                                .split(',')
+                                .map(|origin| origin.trim().trim_end_matches('/').to_string()),
+                        );
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--cors-origin requires a value");
+                    }
+                }
+                "--watch" => {
+                    if i + 1 < args.len() {
+                        config.watch = Some(PathBuf::from(&args[i + 1]));
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--watch requires a value");
+                    }
+                }
+                "--script-root" => {
+                    if i + 1 < args.len() {
+                        config.script_root = Some(PathBuf::from(&args[i + 1]));
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--script-root requires a value");
+                    }
+                }
+                "--library" => {
+                    if i + 1 < args.len() {
+                        config.library = Some(PathBuf::from(&args[i + 1]));
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--library requires a value");
+                    }
+                }
+                "--base-path" => {
+                    if i + 1 < args.len() {
+                        config.base_path = normalize_base_path(&args[i + 1]);
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--base-path requires a value");
+                    }
+                }
+                "--tls-cert" => {
+                    if i + 1 < args.len() {
+                        config.tls_cert = Some(PathBuf::from(&args[i + 1]));
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--tls-cert requires a value");
+                    }
+                }
+                "--tls-key" => {
+                    if i + 1 < args.len() {
+                        config.tls_key = Some(PathBuf::from(&args[i + 1]));
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--tls-key requires a value");
+                    }
+                }
+                "--ping-interval" => {
+                    if i + 1 < args.len() {
+                        config.ping_interval_secs =
+                            args[i + 1].parse().context("Invalid ping interval")?;
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--ping-interval requires a value");
+                    }
+                }
+                "--pong-timeout" => {
+                    if i + 1 < args.len() {
+                        config.pong_timeout_secs =
+                            args[i + 1].parse().context("Invalid pong timeout")?;
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--pong-timeout requires a value");
+                    }
+                }
+                "--rate-limit" => {
+                    if i + 1 < args.len() {
+                        config.rate_limit =
+                            Some(args[i + 1].parse().context("Invalid rate limit")?);
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--rate-limit requires a value");
+                    }
+                }
+                "--max-clients" => {
+                    if i + 1 < args.len() {
+                        config.max_clients =
+                            Some(args[i + 1].parse().context("Invalid max clients")?);
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--max-clients requires a value");
+                    }
+                }
+                "--max-concurrent-broadcasts" => {
+                    if i + 1 < args.len() {
+                        config.max_concurrent_broadcasts = Some(
+                            args[i + 1]
+                                .parse()
+                                .context("Invalid max concurrent broadcasts")?,
+                        );
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--max-concurrent-broadcasts requires a value");
+                    }
+                }
+                "--chunk-size" => {
+                    if i + 1 < args.len() {
+                        config.chunk_size =
+                            Some(args[i + 1].parse().context("Invalid chunk size")?);
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--chunk-size requires a value");
+                    }
+                }
+                "--client-buffer" => {
+                    if i + 1 < args.len() {
+                        config.client_buffer =
+                            args[i + 1].parse().context("Invalid client buffer")?;
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--client-buffer requires a value");
+                    }
+                }
+                "--history-size" => {
+                    if i + 1 < args.len() {
+                        config.history_size =
+                            args[i + 1].parse().context("Invalid history size")?;
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--history-size requires a value");
+                    }
+                }
+                "--debounce-ms" => {
+                    if i + 1 < args.len() {
+                        config.debounce_ms =
+                            Some(args[i + 1].parse().context("Invalid debounce window")?);
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--debounce-ms requires a value");
+                    }
+                }
+                "--result-webhook" => {
+                    if i + 1 < args.len() {
+                        config.result_webhook = Some(args[i + 1].trim().to_string());
+                        i += 2;
+                    } else {
+                        anyhow::bail!("--result-webhook requires a value");
+                    }
+                }
+                "--config" => {
+                    // Already loaded before the other flags so they can override it
+                    i += 2;
+                }
                 _ => {
                     anyhow::bail!(
-                        "Unknown argument: {}\nUsage: {} [--http-port PORT] [--ws-port PORT] [--host HOST]",
+                        "Unknown argument: {}\nUsage: {} [--config PATH] [--http-port PORT] [--ws-port PORT] [--host HOST] [--execute-timeout-secs SECS] [--pid-file PATH] [--quiet] [--log-format text|json] [--log-file PATH] [--log-max-bytes N] [--on-connect CMD] [--on-disconnect CMD] [--json-payloads] [--trust-forwarded] [--trusted-proxy IP[,IP...]] [--breaker-threshold RATE] [--breaker-cooldown-secs SECS] [--ack-timeout-secs SECS] [--api-key KEY] [--queue-on-empty] [--queue-ttl-secs SECS] [--compress] [--compress-responses] [--max-script-bytes N] [--allowed-ext EXT[,EXT...]] [--result-webhook URL] [--cors-origin ORIGIN[,ORIGIN...]] [--tls-cert PATH --tls-key PATH] [--debounce-ms N] [--ping-interval SECS] [--pong-timeout SECS] [--rate-limit N] [--watch PATH] [--script-root DIR] [--single-port] [--broadcast-count] [--history-size N] [--retry-sends] [--max-clients N] [--chunk-size N] [--client-buffer N] [--drop-slow-clients] [--replay-last] [--base-path PATH] [--http-host HOST] [--ws-host HOST] [--timestamp-format rfc3339|epoch_ms|epoch_s] [--max-concurrent-broadcasts N] [--minify] [--on-empty exit|log|none] [--library DIR]",
                         args[i],
                         args[0]
                     );
@@ -71,27 +914,286 @@ impl ServerConfig {
             }
         }
 
-        Ok(Self {
-            http_host,
-            http_port,
-            ws_host,
-            ws_port,
-        })
+        if let Some(host) = http_host {
+            config.http_host = host;
+        }
+        if let Some(host) = ws_host {
+            config.ws_host = host;
+        }
+
+        Ok(())
+    }
+
+    /// Load configuration from a TOML file, with missing keys taking their defaults
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file '{}'", path.display()))?;
+        let mut config: Self = toml::from_str(&contents)
+            .with_context(|| format!("Invalid config file '{}'", path.display()))?;
+        config.allowed_extensions = config
+            .allowed_extensions
+            .iter()
+            .map(|ext| normalize_extension(ext))
+            .collect();
+        config.base_path = normalize_base_path(&config.base_path);
+        Ok(config)
+    }
+
+    /// Check values that parse correctly but make no sense, wherever they came from
+    fn validate(&self) -> Result<()> {
+        // The WebSocket host goes unused when it shares the HTTP server's port
+        let mut hosts = vec![("HTTP", &self.http_host, self.http_port)];
+        if !self.single_port {
+            hosts.push(("WebSocket", &self.ws_host, self.ws_port));
+        }
+        for (label, host, port) in hosts {
+            let resolves = (host.as_str(), port)
+                .to_socket_addrs()
+                .is_ok_and(|mut addrs| addrs.next().is_some());
+            if !resolves {
+                anyhow::bail!("{} host '{}' does not resolve to an address", label, host);
+            }
+        }
+        if self.execute_timeout_secs == 0 {
+            anyhow::bail!("Execute timeout must be greater than 0");
+        }
+        if self.queue_ttl_secs == 0 {
+            anyhow::bail!("Queue TTL must be greater than 0");
+        }
+        if self.allowed_extensions.is_empty() {
+            anyhow::bail!("At least one allowed extension is required");
+        }
+        if self.allowed_extensions.iter().any(|ext| ext == ".") {
+            anyhow::bail!("Allowed extensions must not be empty");
+        }
+        if let Some(url) = &self.result_webhook {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                anyhow::bail!("Result webhook must be an http:// or https:// URL");
+            }
+        }
+        for origin in &self.cors_origins {
+            let host = origin
+                .strip_prefix("http://")
+                .or_else(|| origin.strip_prefix("https://"));
+            if origin != "*" && !host.is_some_and(|host| !host.is_empty() && !host.contains('/')) {
+                anyhow::bail!(
+                    "Invalid CORS origin '{}', expected '*' or a scheme and host like http://localhost:3000",
+                    origin
+                );
+            }
+        }
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(_), None) => anyhow::bail!("--tls-cert requires --tls-key as well"),
+            (None, Some(_)) => anyhow::bail!("--tls-key requires --tls-cert as well"),
+            (Some(cert), Some(key)) => {
+                for path in [cert, key] {
+                    if !path.is_file() {
+                        anyhow::bail!("TLS file '{}' does not exist", path.display());
+                    }
+                }
+            }
+            (None, None) => {}
+        }
+        if self.base_path.contains(['?', '#']) {
+            anyhow::bail!("Base path '{}' can't contain '?' or '#'", self.base_path);
+        }
+        if let Some(path) = &self.watch {
+            if !path.exists() {
+                anyhow::bail!("Watch path '{}' does not exist", path.display());
+            }
+        }
+        if let Some(path) = &self.script_root {
+            if !path.is_dir() {
+                anyhow::bail!("Script root '{}' is not a directory", path.display());
+            }
+        }
+        if let Some(path) = &self.library {
+            if !path.is_dir() {
+                anyhow::bail!("Library '{}' is not a directory", path.display());
+            }
+        }
+        if !(MIN_PING_INTERVAL_SECS..=MAX_PING_INTERVAL_SECS).contains(&self.ping_interval_secs) {
+            anyhow::bail!(
+                "Ping interval must be between {} and {} seconds",
+                MIN_PING_INTERVAL_SECS,
+                MAX_PING_INTERVAL_SECS
+            );
+        }
+        if self.pong_timeout_secs <= self.ping_interval_secs {
+            anyhow::bail!("Pong timeout must be greater than the ping interval");
+        }
+        if self.max_clients == Some(0) {
+            anyhow::bail!("Max clients must be greater than 0");
+        }
+        if self.max_concurrent_broadcasts == Some(0) {
+            anyhow::bail!("Max concurrent broadcasts must be greater than 0");
+        }
+        if self.client_buffer == 0 {
+            anyhow::bail!("Client buffer must be greater than 0");
+        }
+        if self.chunk_size == Some(0) {
+            anyhow::bail!("Chunk size must be greater than 0");
+        }
+        if self.rate_limit == Some(0) {
+            anyhow::bail!("Rate limit must be greater than 0");
+        }
+        if self.debounce_ms == Some(0) {
+            anyhow::bail!("Debounce window must be greater than 0");
+        }
+        if self.log_max_bytes == 0 {
+            anyhow::bail!("Log file size limit must be greater than 0");
+        }
+        if self.max_script_bytes == 0 {
+            anyhow::bail!("Max script size must be greater than 0");
+        }
+        for (flag, hook) in [
+            ("--on-connect", &self.on_connect),
+            ("--on-disconnect", &self.on_disconnect),
+        ] {
+            if hook
+                .as_deref()
+                .is_some_and(|hook| split_command(hook).is_none())
+            {
+                anyhow::bail!("{} has an unclosed quote", flag);
+            }
+        }
+        if self.ack_timeout_secs == 0 {
+            anyhow::bail!("Ack timeout must be greater than 0");
+        }
+        if self.ack_timeout_secs >= self.execute_timeout_secs {
+            anyhow::bail!("Ack timeout must be shorter than the execute timeout");
+        }
+        if let Some(threshold) = self.breaker_threshold {
+            if !(0.0..1.0).contains(&threshold) {
+                anyhow::bail!("Breaker threshold must be at least 0 and below 1");
+            }
+        }
+        if self
+            .api_key
+            .as_deref()
+            .is_some_and(|key| key.trim().is_empty())
+        {
+            anyhow::bail!("API key must not be empty");
+        }
+        Ok(())
+    }
+
+    /// Whether both servers are served over TLS
+    pub fn tls_enabled(&self) -> bool {
+        self.tls_cert.is_some() && self.tls_key.is_some()
+    }
+
+    /// Welcome message describing these settings, sent to clients as they connect
+    pub fn welcome_message(&self) -> WelcomeMessage {
+        WelcomeMessage {
+            msg_type: "welcome".to_string(),
+            protocol: PROTOCOL_VERSION,
+            ping_interval: self.ping_interval_secs,
+            max_script_bytes: self.max_script_bytes,
+            chunk_size: self.chunk_size,
+            compress: self.compress,
+        }
     }
 
     /// Print server information to console
     pub fn print_info(&self) {
         println!("\nUniversal Roblox Executor Proxy Server\n");
-        println!("HTTP Server: http://{}:{}", self.http_host, self.http_port);
-        println!("WebSocket Server: ws://{}:{}", self.ws_host, self.ws_port);
+        let (http_scheme, ws_scheme) = if self.tls_enabled() {
+            ("https", "wss")
+        } else {
+            ("http", "ws")
+        };
+        let base = &self.base_path;
+        println!(
+            "HTTP Server: {}://{}:{}{}",
+            http_scheme, self.http_host, self.http_port, base
+        );
+        if self.single_port {
+            println!(
+                "WebSocket Server: {}://{}:{}{}/{}",
+                ws_scheme, self.http_host, self.http_port, base, SINGLE_PORT_WS_PATH
+            );
+        } else {
+            println!(
+                "WebSocket Server: {}://{}:{}{}",
+                ws_scheme, self.ws_host, self.ws_port, base
+            );
+        }
+        if let Some(path) = &self.watch {
+            println!("Watching: {}", path.display());
+        }
+        if let Some(path) = &self.script_root {
+            println!("Script Root: {}", path.display());
+        }
+        if let Some(path) = &self.library {
+            println!("Library: {}", path.display());
+        }
         println!("\nWaiting for executor clients to connect...");
         println!("\nExample usage (Windows CMD):");
         println!(
-            r#"  curl -X POST http://{}:{}/execute -d "C:\path\to\script.lua""#,
-            self.http_host, self.http_port
+            r#"  curl -X POST {}://{}:{}{}/execute -d "C:\path\to\script.lua""#,
+            http_scheme, self.http_host, self.http_port, base
         );
         println!("\nCheck status:");
-        println!("  curl http://{}:{}/status", self.http_host, self.http_port);
+        println!(
+            "  curl {}://{}:{}{}/status",
+            http_scheme, self.http_host, self.http_port, base
+        );
         println!("\nPress Ctrl+C to stop\n");
     }
 }
+
+/// Give a base path one leading slash and no trailing or repeated ones, so `rbxproxy/`,
+/// `/rbxproxy` and `//rbxproxy//` all become `/rbxproxy`. The root becomes an empty string
+pub fn normalize_base_path(path: &str) -> String {
+    path.split('/')
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| format!("/{}", segment))
+        .collect()
+}
+
+/// Split a hook command into its program and arguments on whitespace
+/// Double or single quotes keep an argument with spaces together, e.g.
+/// `"/opt/my tools/notify.sh" --verbose`. There are no escapes, so Windows paths work unquoted
+/// Returns None if a quote is never closed
+pub fn split_command(command: &str) -> Option<Vec<String>> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut in_part = false;
+    let mut quote = None;
+
+    for c in command.chars() {
+        match quote {
+            Some(open) if c == open => quote = None,
+            Some(_) => current.push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                in_part = true;
+            }
+            None if c.is_whitespace() => {
+                if in_part {
+                    parts.push(std::mem::take(&mut current));
+                    in_part = false;
+                }
+            }
+            None => {
+                current.push(c);
+                in_part = true;
+            }
+        }
+    }
+
+    if quote.is_some() {
+        return None;
+    }
+    if in_part {
+        parts.push(current);
+    }
+    Some(parts)
+}
+
+/// Lowercase an extension and give it a leading dot, so `LUA`, `lua` and `.lua` all match
+pub fn normalize_extension(ext: &str) -> String {
+    format!(".{}", ext.trim().trim_start_matches('.').to_lowercase())
+}
@@ -0,0 +1,53 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+
+/// Drops repeats of the most recent script sent within a short window
+pub struct Debouncer {
+    window: Duration,
+    /// Hash of the last message type, filename and script seen, and when
+    last: Mutex<Option<(u64, Instant)>>,
+}
+
+impl Debouncer {
+    /// Create a debouncer that treats identical scripts within `window` as duplicates
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Check whether the same script was seen within the window, recording it if not
+    pub async fn is_duplicate(&self, msg_type: &str, filename: &str, script: &str) -> bool {
+        let hash = script_hash(msg_type, filename, script);
+        let mut last = self.last.lock().await;
+
+        if let Some((last_hash, seen_at)) = *last {
+            if last_hash == hash && seen_at.elapsed() < self.window {
+                return true;
+            }
+        }
+        *last = Some((hash, Instant::now()));
+        false
+    }
+
+    /// Forget a script so it can be sent again straight away, e.g. after it reached no one
+    pub async fn forget(&self, msg_type: &str, filename: &str, script: &str) {
+        let hash = script_hash(msg_type, filename, script);
+        let mut last = self.last.lock().await;
+        if last.is_some_and(|(last_hash, _)| last_hash == hash) {
+            *last = None;
+        }
+    }
+}
+
+fn script_hash(msg_type: &str, filename: &str, script: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    msg_type.hash(&mut hasher);
+    filename.hash(&mut hasher);
+    script.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,85 @@
+use std::io::Write;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use warp::ws::Message;
+
+use crate::config::CHUNK_MESSAGE_TYPE;
+use crate::types::ChunkMessage;
+
+/// First byte of a binary frame whose remaining bytes are a gzip-compressed JSON message
+pub const GZIP_FRAME_HEADER: u8 = 0x01;
+
+/// Messages smaller than this are sent as text even with compression enabled
+pub const COMPRESS_MIN_BYTES: usize = 1024;
+
+/// Build the WebSocket frame for a JSON message
+/// With `compress` set, large messages become a binary frame: `GZIP_FRAME_HEADER` followed by
+/// the gzip-compressed JSON. Falls back to a text frame if compression fails
+pub fn encode_frame(json: &str, compress: bool) -> Message {
+    if compress && json.len() >= COMPRESS_MIN_BYTES {
+        if let Ok(frame) = gzip_frame(json) {
+            return Message::binary(frame);
+        }
+    }
+    Message::text(json)
+}
+
+/// Build the frames for message `id`, splitting its JSON into `execute_chunk` messages when it is
+/// longer than `chunk_size` bytes. Every frame is encoded as `encode_frame` would
+pub fn encode_frames(
+    json: &str,
+    id: u64,
+    chunk_size: Option<usize>,
+    compress: bool,
+) -> Vec<Message> {
+    let Some(chunk_size) = chunk_size.filter(|&size| json.len() > size) else {
+        return vec![encode_frame(json, compress)];
+    };
+
+    let pieces = split_chunks(json, chunk_size);
+    let total = pieces.len();
+    pieces
+        .into_iter()
+        .chain(std::iter::once(""))
+        .enumerate()
+        .map(|(seq, data)| {
+            let chunk = ChunkMessage {
+                msg_type: CHUNK_MESSAGE_TYPE.to_string(),
+                id,
+                seq,
+                total,
+                data: data.to_string(),
+            };
+            // A struct of strings and integers always serializes
+            let chunk_json = serde_json::to_string(&chunk).unwrap_or_default();
+            encode_frame(&chunk_json, compress)
+        })
+        .collect()
+}
+
+/// Split `text` into pieces of at most `chunk_size` bytes, never inside a UTF-8 character
+/// A piece only goes over the size when `chunk_size` is smaller than a single character
+pub fn split_chunks(text: &str, chunk_size: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let mut end = chunk_size.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let (piece, remainder) = rest.split_at(end);
+        pieces.push(piece);
+        rest = remainder;
+    }
+    pieces
+}
+
+fn gzip_frame(json: &str) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(vec![GZIP_FRAME_HEADER], Compression::default());
+    encoder.write_all(json.as_bytes())?;
+    encoder.finish()
+}
@@ -1,63 +1,133 @@
+use std::collections::HashSet;
 use std::fs;
-use std::path::Path;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
-use chrono::Local;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use bytes::Bytes;
 use futures_util::{SinkExt, StreamExt};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{self, WeakSender};
+use tokio::sync::Notify;
 use warp::http::StatusCode;
-use warp::ws::WebSocket;
+use warp::path::FullPath;
+use warp::ws::{Message, WebSocket, Ws};
+use warp::{Filter, Rejection};
 
-use crate::client_manager::{log, ClientManager};
-use crate::config::ALLOWED_EXTENSIONS;
-use crate::types::{ExecuteMessage, ExecuteResponse, SimpleMessage, StatusResponse};
+use crate::auth::require_api_key;
+use crate::client_manager::{log, warn, AckWait, ClientManager};
+use crate::config::{
+    normalize_extension, ServerConfig, BACKGROUND_TASK_STALE_SECS, BINARY_EXTENSIONS,
+    BINARY_MESSAGE_TYPE, EXECUTE_MESSAGE_TYPES, EXECUTE_RESPONSE_MARGIN_MS, HISTORY_PREVIEW_CHARS,
+    JSON_PAYLOAD_EXTENSION,
+};
+use crate::frame::encode_frames;
+use crate::health::TaskHealth;
+use crate::live_config::LiveConfig;
+use crate::metrics::PROMETHEUS_CONTENT_TYPE;
+use crate::minify::minify_lua;
+use crate::timestamp::Timestamp;
+use crate::types::{
+    AckMessage, ClientListMessage, ClientMessage, ClientsResponse, DisconnectResponse,
+    DrainResponse, ErrorMessage, ExecuteMessage, ExecuteQuery, ExecuteResponse, FileResult,
+    HelloMessage, HistoryEntry, HistoryResponse, JsonPayload, PingResponse, ReloadResponse,
+    StatusQuery, StatusResponse, VersionResponse, WhoamiResponse,
+};
 
 /// Handle WebSocket connections from executor clients
-pub async fn handle_websocket(ws: WebSocket, client_manager: Arc<ClientManager>) {
-    let (mut ws_tx, mut ws_rx) = ws.split();
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+/// `address` is the remote socket address, if warp could tell it
+pub async fn handle_websocket(
+    ws: WebSocket,
+    address: Option<SocketAddr>,
+    client_manager: Arc<ClientManager>,
+) {
+    if client_manager.is_draining() {
+        log("Refused connection while draining");
+        reject_connection(ws, "Server is draining, connect to another instance").await;
+        return;
+    }
+
+    let (tx, mut rx) = mpsc::channel(client_manager.client_buffer());
 
-    // Register client
-    let client_id = client_manager.register(tx).await;
+    // Keep only a weak handle for replies, so the connection closes once the client manager
+    // drops the client's sender
+    let reply_tx = tx.downgrade();
+    let client_id = match client_manager.try_register(tx, address).await {
+        Ok(client_id) => client_id,
+        Err(count) => {
+            warn(&format!(
+                "Refused connection: server full with {} clients",
+                count
+            ));
+            reject_connection(ws, SERVER_FULL_REASON).await;
+            return;
+        }
+    };
+    let (mut ws_tx, mut ws_rx) = ws.split();
 
-    // Spawn task to forward messages from channel to WebSocket
-    let send_task = tokio::spawn(async move {
+    // Spawn task to forward messages from channel to WebSocket, stopping after a close frame and
+    // closing the socket once the channel closes
+    let mut send_task = tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
-            if ws_tx.send(msg).await.is_err() {
-                break;
+            let is_close = msg.is_close();
+            if ws_tx.send(msg).await.is_err() || is_close {
+                return;
             }
         }
+        let _ = ws_tx.send(Message::close()).await;
     });
 
-    // Handle incoming messages from client
-    while let Some(result) = ws_rx.next().await {
+    // Handle incoming messages from client until either side goes away
+    loop {
+        let result = tokio::select! {
+            result = ws_rx.next() => result,
+            _ = &mut send_task => break,
+        };
+        let Some(result) = result else {
+            break;
+        };
         match result {
             Ok(msg) => {
                 if msg.is_text() {
                     if let Ok(text) = msg.to_str() {
-                        // Try to parse as SimpleMessage to check for pong
-                        if let Ok(parsed) = serde_json::from_str::<SimpleMessage>(text) {
-                            if parsed.msg_type == "pong" {
-                                // Update pong time silently (no log)
-                                client_manager.update_pong(client_id).await;
-                            } else {
-                                // Log other message types
-                                log(&format!(
-                                    "Received message from client {}: {}",
-                                    client_id, text
-                                ));
+                        match parse_client_message(text) {
+                            // Update pong time and round-trip time silently (no log)
+                            Ok(ClientMessage::Pong { ts }) => {
+                                client_manager.update_pong(client_id, ts).await;
+                            }
+                            Ok(ClientMessage::Hello(hello)) => {
+                                handle_hello(hello, client_id, &reply_tx, &client_manager).await;
+                            }
+                            Ok(ClientMessage::Ack(ack)) => {
+                                handle_ack(ack, client_id, &client_manager).await;
                             }
-                        } else {
-                            // If parsing fails, just log it
-                            log(&format!(
-                                "Received message from client {}: {}",
-                                client_id, text
-                            ));
+                            Ok(ClientMessage::Result(result)) => {
+                                client_manager.report_result(client_id, result).await;
+                            }
+                            Ok(ClientMessage::ListClients) => {
+                                handle_list_clients(&reply_tx, &client_manager).await;
+                            }
+                            // Clients speaking a protocol we don't know are worth noticing
+                            Err(e) => warn(&format!(
+                                "Malformed message from client {}: {}: {}",
+                                client_manager.label(client_id).await,
+                                e,
+                                text
+                            )),
                         }
                     }
                 } else if msg.is_binary() {
                     log(&format!(
                         "Received binary message from client {}",
-                        client_id
+                        client_manager.label(client_id).await
                     ));
                 }
             }
@@ -70,189 +140,1838 @@ pub async fn handle_websocket(ws: WebSocket, client_manager: Arc<ClientManager>)
     client_manager.unregister(client_id).await;
 }
 
-/// Handle /execute_file endpoint - receives file path and broadcasts script to all clients
+/// Parse a message from a client, explaining what was wrong with it otherwise
+/// When a field of a known message type is the problem the error names it, e.g.
+/// "pong field `ts`: invalid type: string \"x\", expected i64"
+pub fn parse_client_message(text: &str) -> Result<ClientMessage, String> {
+    let value: serde_json::Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let error = match ClientMessage::deserialize(&value) {
+        Ok(message) => return Ok(message),
+        Err(e) => e.to_string(),
+    };
+
+    // Find the field at fault by leaving each out in turn: without it the message either parses
+    // or is only missing that field
+    let (Some(fields), Some(msg_type)) = (
+        value.as_object(),
+        value.get("type").and_then(serde_json::Value::as_str),
+    ) else {
+        return Err(error);
+    };
+    for field in fields.keys().filter(|field| *field != "type") {
+        let mut without = fields.clone();
+        without.remove(field);
+        let culprit = match ClientMessage::deserialize(&serde_json::Value::Object(without)) {
+            Ok(_) => true,
+            Err(e) => e.to_string() == format!("missing field `{}`", field),
+        };
+        if culprit {
+            return Err(format!("{} field `{}`: {}", msg_type, field, error));
+        }
+    }
+    Err(error)
+}
+
+/// Apply a client's hello, storing its name and tags and replying with a welcome carrying the
+/// negotiated ping interval
+async fn handle_hello(
+    hello: HelloMessage,
+    client_id: usize,
+    reply_tx: &WeakSender<Message>,
+    client_manager: &ClientManager,
+) {
+    // Claim the UUID first so the name and tags below override the remembered ones
+    if let Some(uuid) = &hello.client_uuid {
+        if !client_manager.claim_uuid(client_id, uuid).await {
+            warn(&format!("Ignoring blank UUID from client {}", client_id));
+        }
+    }
+    if let Some(name) = &hello.name {
+        if !client_manager.set_name(client_id, name).await {
+            warn(&format!("Ignoring blank name from client {}", client_id));
+        }
+    }
+    if let Some(tags) = &hello.tags {
+        client_manager.set_tags(client_id, tags).await;
+    }
+    if let Some(protocol) = hello.protocol {
+        client_manager.set_protocol(client_id, protocol).await;
+    }
+
+    let ping_interval = match hello.ping_interval {
+        Some(requested) => client_manager.set_ping_interval(client_id, requested).await,
+        None => client_manager.ping_interval(client_id).await,
+    };
+
+    let welcome = client_manager.welcome(ping_interval);
+    if let (Ok(json), Some(tx)) = (serde_json::to_string(&welcome), reply_tx.upgrade()) {
+        let _ = tx.try_send(Message::text(json));
+    }
+}
+
+/// Record a client's ack for an execute message, warning if the script failed
+async fn handle_ack(ack: AckMessage, client_id: usize, client_manager: &ClientManager) {
+    if ack.status != "ok" {
+        warn(&format!(
+            "Client {} failed to run '{}': {}",
+            client_manager.label(client_id).await,
+            ack.filename.as_deref().unwrap_or("unknown"),
+            ack.error.as_deref().unwrap_or(&ack.status)
+        ));
+    }
+
+    // Acks for requests that aren't waiting, or have stopped waiting, are dropped silently
+    client_manager.record_ack(client_id, ack.id);
+}
+
+/// Reply to a client with the same details of its peers that /clients returns
+async fn handle_list_clients(reply_tx: &WeakSender<Message>, client_manager: &ClientManager) {
+    let message = ClientListMessage {
+        msg_type: "client_list".to_string(),
+        clients: client_manager.list_clients().await,
+    };
+    if let (Ok(json), Some(tx)) = (serde_json::to_string(&message), reply_tx.upgrade()) {
+        let _ = tx.try_send(Message::text(json));
+    }
+}
+
+/// Error reason sent to connections refused because `--max-clients` was reached
+const SERVER_FULL_REASON: &str = "server full";
+
+/// Filter answering WebSocket upgrades that didn't match the WebSocket route with a logged 404,
+/// so clients pointed at the wrong URL show up in the logs instead of silently never connecting
+/// `expected` is the path clients should use. Mount this after every other route
+pub fn reject_ws_upgrade(
+    expected: String,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::ws()
+        .and(warp::path::full())
+        .map(move |_ws: Ws, path: FullPath| {
+            warn(&format!(
+                "Rejected WebSocket upgrade on {}, clients should connect to {}",
+                path.as_str(),
+                expected
+            ));
+            let response = ExecuteResponse {
+                error: Some(format!(
+                    "No WebSocket endpoint at {}, connect to {} instead",
+                    path.as_str(),
+                    expected
+                )),
+                ..Default::default()
+            };
+            warp::reply::with_status(warp::reply::json(&response), StatusCode::NOT_FOUND)
+        })
+}
+
+/// Send an error message to a client and close its connection without registering it
+async fn reject_connection(mut ws: WebSocket, reason: &str) {
+    let message = ErrorMessage {
+        msg_type: "error".to_string(),
+        reason: reason.to_string(),
+    };
+
+    if let Ok(json) = serde_json::to_string(&message) {
+        let _ = ws.send(Message::text(json)).await;
+    }
+    let _ = ws.close().await;
+}
+
+/// Handle /execute_file endpoint - receives file path and broadcasts script to all clients,
+/// or to a single client for /execute_file/{id}
+/// The whole request is bounded by the configured execute timeout
 pub async fn handle_execute(
-    body: String,
+    body: Bytes,
+    client_id: Option<usize>,
+    query: ExecuteQuery,
+    ip: Option<IpAddr>,
+    client_manager: Arc<ClientManager>,
+    config: Arc<ServerConfig>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let body = match decode_body(body, "File path is not valid UTF-8") {
+        Ok(body) => body,
+        Err(reply) => return Ok(reply),
+    };
+
+    let requested = match parse_file_list(&body).as_slice() {
+        [path] => format!("'{}'", path),
+        paths => format!("{} files", paths.len()),
+    };
+    match client_id {
+        Some(id) => log(&format!(
+            "Execute request for {} on client {} from {}",
+            requested,
+            id,
+            describe_ip(ip)
+        )),
+        None => log(&format!(
+            "Execute request for {} from {}",
+            requested,
+            describe_ip(ip)
+        )),
+    }
+
+    Ok(respond_within_timeout(
+        execute_file_on(body, client_id, query, client_manager, config.clone()),
+        &config,
+    )
+    .await)
+}
+
+/// Handle /execute_code endpoint - receives script source and broadcasts it to all clients
+/// The whole request is bounded by the configured execute timeout
+pub async fn handle_execute_code(
+    body: Bytes,
+    filename: Option<String>,
+    query: ExecuteQuery,
+    ip: Option<IpAddr>,
     client_manager: Arc<ClientManager>,
+    config: Arc<ServerConfig>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let file_path_str = body.trim();
+    let body = match decode_body(body, SCRIPT_NOT_UTF8) {
+        Ok(body) => body,
+        Err(reply) => return Ok(reply),
+    };
+
+    log(&format!(
+        "Execute request for {} bytes of inline code from {}",
+        body.len(),
+        describe_ip(ip)
+    ));
+
+    Ok(respond_within_timeout(
+        execute_code(body, filename, query, client_manager, config.clone()),
+        &config,
+    )
+    .await)
+}
 
-    // Validate file path provided
-    if file_path_str.is_empty() {
+/// Error reported for scripts that aren't valid UTF-8, rather than replacing the bad bytes
+const SCRIPT_NOT_UTF8: &str = "Script is not valid UTF-8";
+
+/// Decode a request body as strict UTF-8, or build a 400 reply with `error`
+fn decode_body(
+    body: Bytes,
+    error: &str,
+) -> Result<String, warp::reply::WithStatus<warp::reply::Json>> {
+    String::from_utf8(body.to_vec()).map_err(|_| {
         let response = ExecuteResponse {
-            success: false,
-            message: None,
-            error: Some("No file path provided".to_string()),
-            clients_reached: None,
-            total_clients: None,
+            error: Some(error.to_string()),
+            ..Default::default()
         };
-        return Ok(warp::reply::with_status(
-            warp::reply::json(&response),
+        warp::reply::with_status(warp::reply::json(&response), StatusCode::BAD_REQUEST)
+    })
+}
+
+/// Handle /run/{name} endpoint - sends a script from the `--library` directory by name
+/// The whole request is bounded by the configured execute timeout
+pub async fn handle_run(
+    name: String,
+    query: ExecuteQuery,
+    ip: Option<IpAddr>,
+    client_manager: Arc<ClientManager>,
+    config: Arc<ServerConfig>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    log(&format!(
+        "Run request for '{}' from {}",
+        name,
+        describe_ip(ip)
+    ));
+
+    Ok(respond_within_timeout(
+        run_library_script(name, query, client_manager, config.clone()),
+        &config,
+    )
+    .await)
+}
+
+/// Find a script in the library by name and send it the same way as /execute_file
+/// Returns 400 for names that could reach outside the library, and 404 when no file matches
+pub async fn run_library_script(
+    name: String,
+    query: ExecuteQuery,
+    client_manager: Arc<ClientManager>,
+    config: Arc<ServerConfig>,
+) -> (ExecuteResponse, StatusCode) {
+    let (error, status) = match &config.library {
+        None => (
+            "No script library configured, start the server with --library".to_string(),
+            StatusCode::NOT_FOUND,
+        ),
+        Some(_) if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") => (
+            format!("Invalid script name '{}'", name),
             StatusCode::BAD_REQUEST,
-        ));
+        ),
+        Some(library) => match library_script_path(&name, library, &config.allowed_extensions) {
+            Some(path) => {
+                let path = path.to_string_lossy().to_string();
+                return execute_file_on(path, None, query, client_manager, config).await;
+            }
+            None => (
+                format!("No script named '{}' in the library", name),
+                StatusCode::NOT_FOUND,
+            ),
+        },
+    };
+
+    let response = ExecuteResponse {
+        error: Some(error),
+        ..Default::default()
+    };
+    (response, status)
+}
+
+/// Path of the library file for a script name, trying each allowed extension in order
+pub fn library_script_path(name: &str, library: &Path, extensions: &[String]) -> Option<PathBuf> {
+    extensions
+        .iter()
+        .map(|ext| library.join(format!("{}{}", name, ext)))
+        .find(|path| path.is_file())
+}
+
+/// Resolve `path` with `..` and symlinks followed, returning None if it ends up outside `root`
+pub fn resolve_in_script_root(path: &Path, root: &Path) -> std::io::Result<Option<PathBuf>> {
+    let root = fs::canonicalize(root)?;
+    let resolved = fs::canonicalize(path)?;
+    Ok(resolved.starts_with(&root).then_some(resolved))
+}
+
+fn describe_ip(ip: Option<IpAddr>) -> String {
+    ip.map_or_else(|| "unknown address".to_string(), |ip| ip.to_string())
+}
+
+tokio::task_local! {
+    /// When the execute request being handled times out, so waits inside it can end early
+    /// enough to still report what they collected
+    static REQUEST_DEADLINE: tokio::time::Instant;
+}
+
+/// Turn an execute result into a reply, or a 504 if it takes longer than the execute timeout
+/// A request waiting for acks returns its own 504 with the partial results just before then
+async fn respond_within_timeout(
+    execute: impl Future<Output = (ExecuteResponse, StatusCode)>,
+    config: &ServerConfig,
+) -> warp::reply::WithStatus<warp::reply::Json> {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(config.execute_timeout_secs);
+
+    match tokio::time::timeout_at(deadline, REQUEST_DEADLINE.scope(deadline, execute)).await {
+        Ok((response, status)) => warp::reply::with_status(warp::reply::json(&response), status),
+        Err(_) => {
+            warn(&format!(
+                "Execute request timed out after {} seconds",
+                config.execute_timeout_secs
+            ));
+            let response = ExecuteResponse {
+                error: Some(format!(
+                    "Execute request timed out after {} seconds",
+                    config.execute_timeout_secs
+                )),
+                ..Default::default()
+            };
+            warp::reply::with_status(warp::reply::json(&response), StatusCode::GATEWAY_TIMEOUT)
+        }
     }
+}
 
-    let file_path = Path::new(file_path_str);
+/// Validate the file path in the request body, read the script and broadcast it
+/// Returns the response body and status code independently of warp so it can be tested directly
+pub async fn execute_file(
+    body: String,
+    query: ExecuteQuery,
+    client_manager: Arc<ClientManager>,
+    config: Arc<ServerConfig>,
+) -> (ExecuteResponse, StatusCode) {
+    execute_file_on(body, None, query, client_manager, config).await
+}
 
-    // Validate file exists
-    if !file_path.exists() {
+/// Like `execute_file`, but sends the script to a single client when `client_id` is given
+/// Returns 404 if that client isn't connected
+pub async fn execute_file_on(
+    body: String,
+    client_id: Option<usize>,
+    query: ExecuteQuery,
+    client_manager: Arc<ClientManager>,
+    config: Arc<ServerConfig>,
+) -> (ExecuteResponse, StatusCode) {
+    if let Some(error) = validate_query(&query) {
         let response = ExecuteResponse {
-            success: false,
-            message: None,
-            error: Some(format!("File '{}' does not exist", file_path_str)),
-            clients_reached: None,
-            total_clients: None,
+            error: Some(error),
+            ..Default::default()
+        };
+        return (response, StatusCode::BAD_REQUEST);
+    }
+
+    // A single client can't also be sampled, targeted or picked by tag or slot
+    if client_id.is_some()
+        && (query.sample.is_some()
+            || query.target.is_some()
+            || query.tag.is_some()
+            || query.slot.is_some())
+    {
+        let response = ExecuteResponse {
+            error: Some(
+                "A client ID cannot be combined with sample, target, tag or slot".to_string(),
+            ),
+            ..Default::default()
         };
-        return Ok(warp::reply::with_status(
-            warp::reply::json(&response),
+        return (response, StatusCode::BAD_REQUEST);
+    }
+
+    let paths = parse_file_list(&body);
+
+    // Held until the request finishes, so a second request for the same file can't interleave
+    let _in_flight = match client_manager.claim_files(&paths) {
+        Ok(guard) => guard,
+        Err(path) => {
+            let response = ExecuteResponse {
+                error: Some(format!("'{}' is already executing", path)),
+                ..Default::default()
+            };
+            return (response, StatusCode::CONFLICT);
+        }
+    };
+    // Wait for a turn under --max-concurrent-broadcasts before reading anything
+    let _permit = client_manager.broadcast_permit().await;
+
+    match paths.as_slice() {
+        [] => {
+            let response = ExecuteResponse {
+                error: Some("No file path provided".to_string()),
+                ..Default::default()
+            };
+            (response, StatusCode::BAD_REQUEST)
+        }
+        [path] => match load_script_file(path, &query, &client_manager, &config) {
+            Ok(message) => {
+                broadcast_message(message, client_id, query, client_manager.clone(), config).await
+            }
+            Err((error, status)) => {
+                let response = ExecuteResponse {
+                    error: Some(error),
+                    ..Default::default()
+                };
+                (response, status)
+            }
+        },
+        _ => execute_batch(paths, client_id, query, client_manager.clone(), config).await,
+    }
+}
+
+/// Validate a script file and read it into the message sent to clients
+/// Returns the error and status code to report when the file can't be sent
+fn load_script_file(
+    file_path_str: &str,
+    query: &ExecuteQuery,
+    client_manager: &ClientManager,
+    config: &ServerConfig,
+) -> Result<ExecuteMessage, (String, StatusCode)> {
+    let file_path = Path::new(file_path_str);
+
+    // Check and read the resolved path under a script root, so a symlink with an allowed name
+    // can't stand in for a file that isn't, and one swapped after the check can't escape
+    let read_path = match &config.script_root {
+        Some(root) => match resolve_in_script_root(file_path, root) {
+            Ok(Some(resolved)) => resolved,
+            Ok(None) => {
+                return Err((
+                    format!("'{}' is outside the script root", file_path_str),
+                    StatusCode::FORBIDDEN,
+                ));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err((
+                    format!("File '{}' does not exist", file_path_str),
+                    StatusCode::BAD_REQUEST,
+                ));
+            }
+            Err(e) => {
+                return Err((
+                    format!(
+                        "'{}' could not be checked against the script root: {}",
+                        file_path_str, e
+                    ),
+                    StatusCode::FORBIDDEN,
+                ));
+            }
+        },
+        None => file_path.to_path_buf(),
+    };
+
+    // Validate file exists
+    if !read_path.exists() {
+        return Err((
+            format!("File '{}' does not exist", file_path_str),
             StatusCode::BAD_REQUEST,
         ));
     }
 
     // Validate it's a file
-    if !file_path.is_file() {
-        let response = ExecuteResponse {
-            success: false,
-            message: None,
-            error: Some(format!("'{}' is not a file", file_path_str)),
-            clients_reached: None,
-            total_clients: None,
-        };
-        return Ok(warp::reply::with_status(
-            warp::reply::json(&response),
+    if !read_path.is_file() {
+        return Err((
+            format!("'{}' is not a file", file_path_str),
             StatusCode::BAD_REQUEST,
         ));
     }
 
     // Validate extension
-    let extension = file_path
+    let extension = read_path
         .extension()
         .and_then(|e| e.to_str())
-        .map(|e| format!(".{}", e.to_lowercase()))
+        .map(normalize_extension)
         .unwrap_or_default();
 
-    if !ALLOWED_EXTENSIONS.contains(&extension.as_str()) {
-        let response = ExecuteResponse {
-            success: false,
-            message: None,
-            error: Some(format!(
+    let is_json_payload = config.json_payloads && extension == JSON_PAYLOAD_EXTENSION;
+
+    if !config.allowed_extensions.contains(&extension) && !is_json_payload {
+        return Err((
+            format!(
                 "File must be one of {:?}, got '{}'",
-                ALLOWED_EXTENSIONS, extension
-            )),
-            clients_reached: None,
-            total_clients: None,
-        };
-        return Ok(warp::reply::with_status(
-            warp::reply::json(&response),
+                config.allowed_extensions, extension
+            ),
             StatusCode::BAD_REQUEST,
         ));
     }
 
-    // Read file contents
-    let code = match fs::read_to_string(file_path) {
-        Ok(content) => content,
+    // Check the size before reading so huge files never reach memory
+    let size = match fs::metadata(&read_path) {
+        Ok(metadata) => metadata.len(),
         Err(e) => {
-            let response = ExecuteResponse {
-                success: false,
-                message: None,
-                error: Some(format!("Error reading file: {}", e)),
-                clients_reached: None,
-                total_clients: None,
-            };
-            return Ok(warp::reply::with_status(
-                warp::reply::json(&response),
+            return Err((
+                format!("Error reading file: {}", e),
                 StatusCode::INTERNAL_SERVER_ERROR,
             ));
         }
     };
+    if let Some(error) = check_script_size(size, config) {
+        return Err((error, StatusCode::PAYLOAD_TOO_LARGE));
+    }
 
     let filename = file_path
         .file_name()
         .and_then(|n| n.to_str())
-        .unwrap_or("unknown");
-
-    // Create message
-    let message = ExecuteMessage {
-        msg_type: "execute".to_string(),
-        script: code,
-        filename: filename.to_string(),
-        timestamp: Local::now().to_rfc3339(),
-    };
+        .unwrap_or("unknown")
+        .to_string();
 
-    let message_json = match serde_json::to_string(&message) {
-        Ok(json) => json,
+    // Read file contents
+    let bytes = match fs::read(&read_path) {
+        Ok(bytes) => bytes,
         Err(e) => {
-            let response = ExecuteResponse {
-                success: false,
-                message: None,
-                error: Some(format!("Error serializing message: {}", e)),
-                clients_reached: None,
-                total_clients: None,
-            };
-            return Ok(warp::reply::with_status(
-                warp::reply::json(&response),
+            return Err((
+                format!("Error reading file: {}", e),
                 StatusCode::INTERNAL_SERVER_ERROR,
             ));
         }
     };
 
-    // Broadcast to all clients
-    let (successful, total) = client_manager.broadcast(&message_json).await;
+    // Bytecode skips text decoding entirely
+    if BINARY_EXTENSIONS.contains(&extension.as_str()) {
+        return Ok(binary_message(&bytes, filename, client_manager));
+    }
 
-    if total == 0 {
-        let response = ExecuteResponse {
-            success: false,
-            message: None,
-            error: Some("No clients connected".to_string()),
-            clients_reached: Some(0),
-            total_clients: Some(0),
+    let Ok(code) = String::from_utf8(bytes) else {
+        return Err((SCRIPT_NOT_UTF8.to_string(), StatusCode::BAD_REQUEST));
+    };
+
+    // Create message, either wrapping the raw script or from a structured payload
+    let message = if is_json_payload {
+        let mut payload = match serde_json::from_str::<JsonPayload>(&code) {
+            Ok(payload) => payload,
+            Err(e) => {
+                return Err((
+                    format!("Invalid JSON payload: {}", e),
+                    StatusCode::BAD_REQUEST,
+                ));
+            }
         };
-        Ok(warp::reply::with_status(
-            warp::reply::json(&response),
-            StatusCode::SERVICE_UNAVAILABLE,
-        ))
-    } else if successful == total {
+
+        let msg_type = query
+            .msg_type
+            .clone()
+            .or(payload.msg_type)
+            .unwrap_or_else(|| EXECUTE_MESSAGE_TYPES[0].to_string());
+        if !EXECUTE_MESSAGE_TYPES.contains(&msg_type.as_str()) {
+            return Err((
+                format!(
+                    "Message type must be one of {:?}, got '{}'",
+                    EXECUTE_MESSAGE_TYPES, msg_type
+                ),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+        if config.minify {
+            payload.script = minify_lua(&payload.script);
+        }
+
+        ExecuteMessage {
+            msg_type,
+            id: client_manager.next_execution_id(),
+            line_ending: line_ending(&payload.script).to_string(),
+            byte_length: payload.script.len(),
+            sha256: sha256_hex(payload.script.as_bytes()),
+            script: payload.script,
+            script_b64: None,
+            filename: payload.filename.unwrap_or(filename),
+            timestamp: Timestamp::now(),
+            args: payload.args,
+            vars: payload.vars,
+        }
+    } else if config.minify {
+        script_message(minify_lua(&code), filename, query, client_manager)
+    } else {
+        script_message(code, filename, query, client_manager)
+    };
+
+    Ok(message)
+}
+
+/// Split an /execute_file body into the paths it names, either a JSON array of strings or
+/// one path per line
+/// Blank lines are skipped, and a body that isn't a JSON array is read as lines, so a single
+/// path starting with `[` still works
+pub fn parse_file_list(body: &str) -> Vec<String> {
+    let body = body.trim();
+    if body.starts_with('[') {
+        if let Ok(paths) = serde_json::from_str::<Vec<String>>(body) {
+            return paths
+                .into_iter()
+                .map(|path| path.trim().to_string())
+                .filter(|path| !path.is_empty())
+                .collect();
+        }
+    }
+    body.lines()
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Send several files in order, each as its own execute message
+/// Every file is validated first, and unless `partial` is set nothing is sent when any of them
+/// fails. Sending stops at the first file that doesn't reach its clients, so later scripts never
+/// run without the ones before them
+async fn execute_batch(
+    paths: Vec<String>,
+    client_id: Option<usize>,
+    mut query: ExecuteQuery,
+    client_manager: Arc<ClientManager>,
+    config: Arc<ServerConfig>,
+) -> (ExecuteResponse, StatusCode) {
+    // Each file must reach the same clients, which the least recently targeted can't promise
+    if query.target.is_some() {
         let response = ExecuteResponse {
-            success: true,
-            message: Some(format!(
-                "Script '{}' sent to all connected clients",
-                filename
-            )),
-            error: None,
-            clients_reached: Some(successful),
-            total_clients: Some(total),
+            error: Some("target cannot be combined with multiple files".to_string()),
+            ..Default::default()
         };
-        Ok(warp::reply::with_status(
-            warp::reply::json(&response),
-            StatusCode::OK,
-        ))
-    } else {
+        return (response, StatusCode::BAD_REQUEST);
+    }
+    if query.sample.is_some() && query.seed.is_none() {
+        query.seed = Some(rand::random());
+    }
+
+    let total = paths.len();
+    let loaded: Vec<_> = paths
+        .iter()
+        .map(|path| load_script_file(path, &query, &client_manager, &config))
+        .collect();
+    let invalid = loaded.iter().filter(|result| result.is_err()).count();
+    if invalid > 0 && !query.partial.unwrap_or(false) {
+        let files = paths
+            .into_iter()
+            .zip(loaded)
+            .map(|(path, result)| FileResult {
+                path,
+                response: ExecuteResponse {
+                    error: Some(result.err().map_or_else(
+                        || "Not sent, another file failed validation".to_string(),
+                        |(error, _)| error,
+                    )),
+                    ..Default::default()
+                },
+            })
+            .collect();
         let response = ExecuteResponse {
-            success: false,
-            message: None,
             error: Some(format!(
-                "Script '{}' only reached {}/{} clients",
-                filename, successful, total
+                "{}/{} files failed validation, nothing was sent",
+                invalid, total
             )),
-            clients_reached: Some(successful),
-            total_clients: Some(total),
+            files: Some(files),
+            ..Default::default()
         };
-        Ok(warp::reply::with_status(
-            warp::reply::json(&response),
-            StatusCode::MULTI_STATUS,
-        ))
+        return (response, StatusCode::BAD_REQUEST);
+    }
+
+    let mut files = Vec::with_capacity(total);
+    let mut sent = 0;
+    let mut first_failure = None;
+    for (path, result) in paths.into_iter().zip(loaded) {
+        let response = match result {
+            Ok(_) if first_failure.is_some() => ExecuteResponse {
+                error: Some("Not sent, an earlier file failed".to_string()),
+                ..Default::default()
+            },
+            Ok(message) => {
+                let (response, status) = broadcast_message(
+                    message,
+                    client_id,
+                    query.clone(),
+                    client_manager.clone(),
+                    config.clone(),
+                )
+                .await;
+                if response.success {
+                    sent += 1;
+                } else {
+                    first_failure = Some(status);
+                }
+                response
+            }
+            // Only reached with `partial`, where the valid files are still sent
+            Err((error, _)) => ExecuteResponse {
+                error: Some(error),
+                ..Default::default()
+            },
+        };
+        files.push(FileResult { path, response });
     }
+
+    let (message, error, status) = if sent == total {
+        (Some(format!("Sent {} files", total)), None, StatusCode::OK)
+    } else if sent == 0 {
+        let status = first_failure.unwrap_or(StatusCode::BAD_REQUEST);
+        (
+            None,
+            Some(format!("None of the {} files were sent", total)),
+            status,
+        )
+    } else {
+        (
+            None,
+            Some(format!("Only sent {}/{} files", sent, total)),
+            StatusCode::MULTI_STATUS,
+        )
+    };
+    let response = ExecuteResponse {
+        success: sent == total,
+        message,
+        error,
+        files: Some(files),
+        ..Default::default()
+    };
+    (response, status)
 }
 
-/// Handle /status endpoint - returns server status and client count
-pub async fn handle_status(
+/// Broadcast script source taken directly from the request body
+/// `filename` defaults to `inline.lua` when the caller doesn't name the script
+pub async fn execute_code(
+    body: String,
+    filename: Option<String>,
+    query: ExecuteQuery,
     client_manager: Arc<ClientManager>,
-) -> Result<impl warp::Reply, warp::Rejection> {
-    let status = StatusResponse {
-        status: "running".to_string(),
-        connected_clients: client_manager.client_count().await,
-        timestamp: Local::now().to_rfc3339(),
+    config: Arc<ServerConfig>,
+) -> (ExecuteResponse, StatusCode) {
+    if let Some(error) = validate_query(&query) {
+        let response = ExecuteResponse {
+            error: Some(error),
+            ..Default::default()
+        };
+        return (response, StatusCode::BAD_REQUEST);
+    }
+
+    // Validate code provided
+    if body.trim().is_empty() {
+        let response = ExecuteResponse {
+            error: Some("No code provided".to_string()),
+            ..Default::default()
+        };
+        return (response, StatusCode::BAD_REQUEST);
+    }
+
+    if let Some(error) = check_script_size(body.len() as u64, &config) {
+        let response = ExecuteResponse {
+            error: Some(error),
+            ..Default::default()
+        };
+        return (response, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let filename = filename.unwrap_or_else(|| INLINE_FILENAME.to_string());
+    let _permit = client_manager.broadcast_permit().await;
+    let script = if config.minify {
+        minify_lua(&body)
+    } else {
+        body
     };
+    let message = script_message(script, filename, &query, &client_manager);
 
-    Ok(warp::reply::json(&status))
+    broadcast_message(message, None, query, client_manager.clone(), config).await
+}
+
+/// Filename reported to clients for inline code sent without an X-Filename header
+const INLINE_FILENAME: &str = "inline.lua";
+
+/// Reject scripts over the configured size limit
+/// Returns the error to report when `size` is too large
+fn check_script_size(size: u64, config: &ServerConfig) -> Option<String> {
+    if size <= config.max_script_bytes {
+        return None;
+    }
+    Some(format!(
+        "Script is {} bytes, over the {} byte limit",
+        size, config.max_script_bytes
+    ))
+}
+
+/// Check the query parameters shared by the execute endpoints
+/// Returns the error to report when they are invalid
+fn validate_query(query: &ExecuteQuery) -> Option<String> {
+    // Validate message type override
+    if let Some(msg_type) = &query.msg_type {
+        if !EXECUTE_MESSAGE_TYPES.contains(&msg_type.as_str()) {
+            return Some(format!(
+                "Message type must be one of {:?}, got '{}'",
+                EXECUTE_MESSAGE_TYPES, msg_type
+            ));
+        }
+    }
+
+    // Validate sample fraction
+    if let Some(sample) = query.sample {
+        if !(sample > 0.0 && sample <= 1.0) {
+            return Some(format!(
+                "Sample must be greater than 0 and at most 1, got {}",
+                sample
+            ));
+        }
+    }
+
+    // Validate tag selection
+    if let Some(tag) = &query.tag {
+        if tag.trim().is_empty() {
+            return Some("Tag must not be empty".to_string());
+        } else if query.sample.is_some() {
+            return Some("Tag and sample cannot be combined".to_string());
+        }
+    }
+
+    // Validate slot selection
+    if query.slot.is_some()
+        && (query.sample.is_some() || query.target.is_some() || query.tag.is_some())
+    {
+        return Some("Slot cannot be combined with sample, target or tag".to_string());
+    }
+
+    // Validate target selection
+    if let Some(target) = &query.target {
+        if target != LRU_TARGET {
+            return Some(format!("Target must be '{}', got '{}'", LRU_TARGET, target));
+        } else if query.sample.is_some() {
+            return Some("Target and sample cannot be combined".to_string());
+        } else if query.tag.is_some() {
+            return Some("Target and tag cannot be combined".to_string());
+        } else if query.count.unwrap_or_default() == 0 {
+            return Some("Target requires a count greater than 0".to_string());
+        }
+    }
+
+    None
+}
+
+/// Wrap raw script source in an execute message
+fn script_message(
+    script: String,
+    filename: String,
+    query: &ExecuteQuery,
+    client_manager: &ClientManager,
+) -> ExecuteMessage {
+    ExecuteMessage {
+        msg_type: query
+            .msg_type
+            .clone()
+            .unwrap_or_else(|| EXECUTE_MESSAGE_TYPES[0].to_string()),
+        id: client_manager.next_execution_id(),
+        line_ending: line_ending(&script).to_string(),
+        byte_length: script.len(),
+        sha256: sha256_hex(script.as_bytes()),
+        script,
+        script_b64: None,
+        filename,
+        timestamp: Timestamp::now(),
+        args: None,
+        vars: None,
+    }
+}
+
+/// Wrap precompiled bytecode in an `execute_binary` message
+fn binary_message(
+    bytecode: &[u8],
+    filename: String,
+    client_manager: &ClientManager,
+) -> ExecuteMessage {
+    ExecuteMessage {
+        msg_type: BINARY_MESSAGE_TYPE.to_string(),
+        id: client_manager.next_execution_id(),
+        script: String::new(),
+        script_b64: Some(BASE64_STANDARD.encode(bytecode)),
+        filename,
+        line_ending: line_ending("").to_string(),
+        byte_length: bytecode.len(),
+        sha256: sha256_hex(bytecode),
+        timestamp: Timestamp::now(),
+        args: None,
+        vars: None,
+    }
+}
+
+/// Hex-encoded SHA-256 of a script's bytes
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Describe the line endings a script uses, without changing them
+fn line_ending(script: &str) -> &'static str {
+    let crlf = script.matches("\r\n").count();
+    let lf = script.matches('\n').count() - crlf;
+    match (lf, crlf) {
+        (0, 0) => "none",
+        (_, 0) => "lf",
+        (0, _) => "crlf",
+        _ => "mixed",
+    }
+}
+
+/// Broadcast an execute message to the clients chosen by the query and build the response
+/// Shared by the execute endpoints so they all respond in the same shape
+async fn broadcast_message(
+    message: ExecuteMessage,
+    client_id: Option<usize>,
+    query: ExecuteQuery,
+    client_manager: Arc<ClientManager>,
+    config: Arc<ServerConfig>,
+) -> (ExecuteResponse, StatusCode) {
+    let execution_id = message.id;
+    let filename = message.filename.clone();
+
+    let message_json = match serde_json::to_string(&message) {
+        Ok(json) => json,
+        Err(e) => {
+            let response = ExecuteResponse {
+                error: Some(format!("Error serializing message: {}", e)),
+                ..Default::default()
+            };
+            return (response, StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    // A dry run stops here, once the script has passed every check
+    if query.dry_run.unwrap_or(false) {
+        let response = ExecuteResponse {
+            success: true,
+            message: Some(format!(
+                "Script '{}' is valid, dry run so not sent",
+                filename
+            )),
+            ..Default::default()
+        };
+        return (response, StatusCode::OK);
+    }
+
+    // Refuse to broadcast while the circuit breaker is open, or half-open and already testing
+    // recovery with another broadcast. A trial is held until this broadcast's outcome is in
+    let _trial = match client_manager.check_circuit().await {
+        Ok(trial) => trial,
+        Err(retry_secs) => {
+            let response = ExecuteResponse {
+                error: Some(format!(
+                    "Circuit open after repeated broadcast failures, retry in {} seconds",
+                    retry_secs
+                )),
+                ..Default::default()
+            };
+            return (response, StatusCode::SERVICE_UNAVAILABLE);
+        }
+    };
+
+    // A slot picks a single client, just like a client ID
+    let client_id = match query.slot {
+        Some(slot) => match client_manager.client_in_slot(slot).await {
+            Some(id) => Some(id),
+            None => {
+                let response = ExecuteResponse {
+                    error: Some(format!("No client in slot {}", slot)),
+                    ..Default::default()
+                };
+                return (response, StatusCode::NOT_FOUND);
+            }
+        },
+        None => client_id,
+    };
+
+    // A script aimed at one client that speaks too old a protocol is refused outright
+    if let (Some(id), Some(min_protocol)) = (client_id, query.min_protocol) {
+        let (_, skipped) = client_manager
+            .split_by_protocol(Some(&[id]), min_protocol)
+            .await;
+        if !skipped.is_empty() {
+            let response = ExecuteResponse {
+                error: Some(format!(
+                    "Client {} speaks a protocol older than {}",
+                    id, min_protocol
+                )),
+                ..Default::default()
+            };
+            return (response, StatusCode::CONFLICT);
+        }
+    }
+
+    // Skip repeats of a script that was just broadcast, e.g. from a file watcher firing twice.
+    // Scripts aimed at particular clients are never debounced, as they may be going elsewhere
+    let targeted = client_id.is_some()
+        || query.tag.is_some()
+        || query.sample.is_some()
+        || query.target.is_some()
+        || query.min_protocol.is_some();
+    if !targeted
+        && client_manager
+            .is_debounced(
+                &message.msg_type,
+                &message.filename,
+                script_content(&message),
+            )
+            .await
+    {
+        log(&format!("Debounced duplicate of '{}'", filename));
+        let response = ExecuteResponse {
+            success: true,
+            message: Some(format!(
+                "Script '{}' debounced, an identical copy was just sent",
+                filename
+            )),
+            ..Default::default()
+        };
+        return (response, StatusCode::OK);
+    }
+
+    // Broadcast to all clients, or to a random sample, least recently targeted or tagged subset
+    let selected_clients = if let Some(tag) = &query.tag {
+        Some(client_manager.client_ids_with_tag(tag.trim()).await)
+    } else if let Some(sample) = query.sample {
+        Some(sample_clients(&client_manager, sample, query.seed).await)
+    } else if query.target.is_some() {
+        let count = query.count.unwrap_or_default();
+        Some(client_manager.least_recently_targeted(count).await)
+    } else {
+        None
+    };
+
+    // Leave out clients below the minimum protocol, reporting which were skipped
+    let (selected_clients, skipped_clients) = match query.min_protocol {
+        Some(min_protocol) if client_id.is_none() => {
+            let (eligible, skipped) = client_manager
+                .split_by_protocol(selected_clients.as_deref(), min_protocol)
+                .await;
+            (Some(eligible), (!skipped.is_empty()).then_some(skipped))
+        }
+        _ => (selected_clients, None),
+    };
+
+    // Start listening before sending so fast acks aren't missed
+    let wait_for_acks = query.wait_for_acks.unwrap_or(false);
+    let (acks, expected_acks) = if wait_for_acks {
+        let expected = match (client_id, &selected_clients) {
+            (Some(id), _) => vec![id],
+            (None, Some(ids)) => ids.clone(),
+            (None, None) => client_manager.client_ids().await,
+        };
+        (Some(client_manager.expect_acks(execution_id)), expected)
+    } else {
+        (None, Vec::new())
+    };
+
+    let frames = encode_frames(
+        &message_json,
+        execution_id,
+        config.chunk_size,
+        config.compress,
+    );
+    let targets = client_id
+        .map(|id| vec![id])
+        .or_else(|| selected_clients.clone());
+    let (successful, total, failed_clients, disconnected_clients) = client_manager
+        .send_frames(targets.as_deref(), &frames)
+        .await;
+    client_manager
+        .record_history(history_entry(&message, successful, total, &failed_clients))
+        .await;
+    if targets.is_none() {
+        client_manager
+            .remember_script(&filename, frames.clone())
+            .await;
+    }
+
+    // Tagged scripts aren't queued, since the next client to connect may not carry the tag,
+    // and neither are scripts with a minimum protocol for the same reason
+    let queued = total == 0
+        && client_id.is_none()
+        && query.tag.is_none()
+        && query.min_protocol.is_none()
+        && client_manager.queue_message(frames).await;
+
+    // A script that reached no one may be retried straight away
+    if successful == 0 && !queued {
+        client_manager
+            .forget_debounced(
+                &message.msg_type,
+                &message.filename,
+                script_content(&message),
+            )
+            .await;
+    }
+
+    if let (Some(id), 0) = (client_id, successful) {
+        let response = ExecuteResponse {
+            error: Some(format!("Client {} not connected", id)),
+            ..Default::default()
+        };
+        return (response, StatusCode::NOT_FOUND);
+    }
+
+    // Dropping the ack wait, on any path, stops collecting acks for this execution
+    let (acknowledged, timed_out) = match acks {
+        Some(acks) if successful > 0 => {
+            let (acked, timed_out) = collect_acks(
+                &client_manager,
+                acks,
+                &expected_acks,
+                successful,
+                Duration::from_secs(config.ack_timeout_secs),
+            )
+            .await;
+            (Some(acked), timed_out)
+        }
+        _ => (None, false),
+    };
+
+    // The execute timeout ran out while waiting for acks, so report what was collected so far
+    if timed_out {
+        client_manager.metrics().record_execute();
+        let response = ExecuteResponse {
+            error: Some(format!(
+                "Execute request timed out after {} seconds waiting for acks",
+                config.execute_timeout_secs
+            )),
+            clients_reached: Some(successful),
+            total_clients: Some(total),
+            selected_clients,
+            skipped_clients,
+            acknowledged,
+            failed_clients: non_empty(failed_clients),
+            disconnected_clients: non_empty(disconnected_clients),
+            ..Default::default()
+        };
+        return (response, StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    if queued {
+        let response = ExecuteResponse {
+            success: true,
+            message: Some(format!(
+                "Script '{}' queued for the next client to connect",
+                filename
+            )),
+            clients_reached: Some(0),
+            total_clients: Some(0),
+            ..Default::default()
+        };
+        (response, StatusCode::ACCEPTED)
+    } else if total == 0 {
+        client_manager.metrics().record_failed_broadcast();
+        let response = ExecuteResponse {
+            error: Some(match (&query.tag, query.min_protocol) {
+                (Some(tag), _) => format!("No clients tagged '{}' connected", tag.trim()),
+                (None, Some(min_protocol)) => {
+                    format!("No clients at protocol {} or above connected", min_protocol)
+                }
+                (None, None) => "No clients connected".to_string(),
+            }),
+            clients_reached: Some(0),
+            total_clients: Some(0),
+            selected_clients,
+            skipped_clients,
+            ..Default::default()
+        };
+        (response, StatusCode::SERVICE_UNAVAILABLE)
+    } else if failed_clients.is_empty() && successful > 0 {
+        client_manager.metrics().record_execute();
+        let sent = match (client_id, &query.tag) {
+            (Some(id), _) => format!("Script '{}' sent to client {}", filename, id),
+            (None, Some(tag)) => {
+                format!(
+                    "Script '{}' sent to clients tagged '{}'",
+                    filename,
+                    tag.trim()
+                )
+            }
+            (None, None) => match query.min_protocol {
+                Some(min_protocol) => format!(
+                    "Script '{}' sent to clients at protocol {} or above",
+                    filename, min_protocol
+                ),
+                None => format!("Script '{}' sent to all connected clients", filename),
+            },
+        };
+        let response = ExecuteResponse {
+            success: true,
+            message: Some(sent + &disconnected_note(&disconnected_clients)),
+            clients_reached: Some(successful),
+            total_clients: Some(total),
+            selected_clients,
+            skipped_clients,
+            acknowledged,
+            disconnected_clients: non_empty(disconnected_clients),
+            ..Default::default()
+        };
+        (response, StatusCode::OK)
+    } else {
+        client_manager.metrics().record_execute();
+        client_manager.metrics().record_failed_broadcast();
+        let response = ExecuteResponse {
+            error: Some(format!(
+                "Script '{}' only reached {}/{} clients{}",
+                filename,
+                successful,
+                total,
+                disconnected_note(&disconnected_clients)
+            )),
+            clients_reached: Some(successful),
+            total_clients: Some(total),
+            selected_clients,
+            skipped_clients,
+            acknowledged,
+            failed_clients: Some(failed_clients),
+            disconnected_clients: non_empty(disconnected_clients),
+            ..Default::default()
+        };
+        (response, StatusCode::MULTI_STATUS)
+    }
+}
+
+/// The script text, or the encoded bytecode for binary messages, for telling scripts apart
+fn script_content(message: &ExecuteMessage) -> &str {
+    message.script_b64.as_deref().unwrap_or(&message.script)
+}
+
+/// Note added to a broadcast's message when clients went away while it was being sent, so a
+/// client leaving isn't mistaken for a failed send
+fn disconnected_note(disconnected_clients: &[usize]) -> String {
+    match disconnected_clients.len() {
+        0 => String::new(),
+        1 => " (1 client disconnected during broadcast)".to_string(),
+        count => format!(" ({} clients disconnected during broadcast)", count),
+    }
+}
+
+/// The IDs, or None when there are none so the field is left out of the response
+fn non_empty(ids: Vec<usize>) -> Option<Vec<usize>> {
+    (!ids.is_empty()).then_some(ids)
+}
+
+/// Summarize a sent execute message for /history
+fn history_entry(
+    message: &ExecuteMessage,
+    clients_reached: usize,
+    total_clients: usize,
+    failed_clients: &[usize],
+) -> HistoryEntry {
+    HistoryEntry {
+        id: message.id,
+        msg_type: message.msg_type.clone(),
+        filename: message.filename.clone(),
+        timestamp: message.timestamp.clone(),
+        script_preview: message.script.chars().take(HISTORY_PREVIEW_CHARS).collect(),
+        script_bytes: message.byte_length,
+        clients_reached,
+        total_clients,
+        failed_clients: failed_clients.to_vec(),
+    }
+}
+
+/// Wait until every reached client has acked the execute message or the ack timeout passes
+/// The wait also ends shortly before the request's execute timeout, if that comes first
+/// Returns the number of clients that acked, and whether the execute timeout cut the wait short
+async fn collect_acks(
+    client_manager: &ClientManager,
+    mut acks: AckWait<'_>,
+    expected: &[usize],
+    reached: usize,
+    timeout: Duration,
+) -> (usize, bool) {
+    let ack_deadline = tokio::time::Instant::now() + timeout;
+    let request_deadline = REQUEST_DEADLINE
+        .try_with(|deadline| *deadline - Duration::from_millis(EXECUTE_RESPONSE_MARGIN_MS))
+        .ok()
+        .filter(|deadline| *deadline < ack_deadline);
+    let deadline = request_deadline.unwrap_or(ack_deadline);
+
+    let mut acked = HashSet::new();
+    while acked.len() < reached {
+        match tokio::time::timeout_at(deadline, acks.recv()).await {
+            Ok(Some(client_id)) => {
+                acked.insert(client_id);
+            }
+            _ => break,
+        }
+    }
+    drop(acks);
+    let timed_out = request_deadline.is_some() && acked.len() < reached;
+
+    // Clients that failed to receive the message were removed during the broadcast
+    let connected = client_manager.client_ids().await;
+    let missing: Vec<usize> = expected
+        .iter()
+        .filter(|id| !acked.contains(*id) && connected.contains(*id))
+        .copied()
+        .collect();
+    if missing.is_empty() {
+        log(&format!(
+            "Broadcast acked by {}/{} clients",
+            acked.len(),
+            reached
+        ));
+    } else {
+        log(&format!(
+            "Broadcast acked by {}/{} clients, no ack from {:?}",
+            acked.len(),
+            reached,
+            missing
+        ));
+    }
+
+    (acked.len(), timed_out)
+}
+
+/// Target value selecting the clients that have gone longest without a broadcast
+const LRU_TARGET: &str = "lru";
+
+/// Pick a random fraction of the connected clients, rounding up so at least one is chosen
+/// The same seed picks the same clients as long as the connected set is unchanged
+async fn sample_clients(
+    client_manager: &ClientManager,
+    sample: f64,
+    seed: Option<u64>,
+) -> Vec<usize> {
+    let ids = client_manager.client_ids().await;
+    let count = (ids.len() as f64 * sample).ceil() as usize;
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    let mut sampled: Vec<usize> = ids.choose_multiple(&mut rng, count).copied().collect();
+    sampled.sort_unstable();
+    sampled
+}
+
+/// Handle /broadcast endpoint - sends a custom JSON control message to every client as-is
+pub async fn handle_broadcast(
+    body: Bytes,
+    ip: Option<IpAddr>,
+    client_manager: Arc<ClientManager>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    log(&format!(
+        "Broadcast request for {} bytes from {}",
+        body.len(),
+        describe_ip(ip)
+    ));
+
+    let (response, status) = broadcast_control(&body, &client_manager).await;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        status,
+    ))
+}
+
+/// Validate a control message is a JSON object with a string `type` and broadcast it unchanged
+/// Returns the response body and status code independently of warp so it can be tested directly
+pub async fn broadcast_control(
+    body: &[u8],
+    client_manager: &ClientManager,
+) -> (ExecuteResponse, StatusCode) {
+    let msg_type = match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(serde_json::Value::Object(fields)) => match fields.get("type") {
+            Some(serde_json::Value::String(msg_type)) if !msg_type.trim().is_empty() => {
+                msg_type.clone()
+            }
+            _ => {
+                let response = ExecuteResponse {
+                    error: Some("Message must have a non-empty string 'type' field".to_string()),
+                    ..Default::default()
+                };
+                return (response, StatusCode::BAD_REQUEST);
+            }
+        },
+        Ok(_) => {
+            let response = ExecuteResponse {
+                error: Some("Message must be a JSON object".to_string()),
+                ..Default::default()
+            };
+            return (response, StatusCode::BAD_REQUEST);
+        }
+        Err(e) => {
+            let response = ExecuteResponse {
+                error: Some(format!("Invalid JSON: {}", e)),
+                ..Default::default()
+            };
+            return (response, StatusCode::BAD_REQUEST);
+        }
+    };
+
+    // Valid JSON is always valid UTF-8
+    let message = String::from_utf8_lossy(body);
+    let (successful, total, failed_clients, disconnected_clients) =
+        client_manager.broadcast(&message).await;
+
+    if total == 0 {
+        let response = ExecuteResponse {
+            error: Some("No clients connected".to_string()),
+            clients_reached: Some(0),
+            total_clients: Some(0),
+            ..Default::default()
+        };
+        (response, StatusCode::SERVICE_UNAVAILABLE)
+    } else if failed_clients.is_empty() && successful > 0 {
+        let response = ExecuteResponse {
+            success: true,
+            message: Some(format!(
+                "Message '{}' sent to all connected clients{}",
+                msg_type,
+                disconnected_note(&disconnected_clients)
+            )),
+            clients_reached: Some(successful),
+            total_clients: Some(total),
+            disconnected_clients: non_empty(disconnected_clients),
+            ..Default::default()
+        };
+        (response, StatusCode::OK)
+    } else {
+        let response = ExecuteResponse {
+            error: Some(format!(
+                "Message '{}' only reached {}/{} clients{}",
+                msg_type,
+                successful,
+                total,
+                disconnected_note(&disconnected_clients)
+            )),
+            clients_reached: Some(successful),
+            total_clients: Some(total),
+            failed_clients: Some(failed_clients),
+            disconnected_clients: non_empty(disconnected_clients),
+            ..Default::default()
+        };
+        (response, StatusCode::MULTI_STATUS)
+    }
+}
+
+/// Handle /status endpoint - returns server status, client count and background task health
+/// With `?tag=`, only clients carrying that tag are counted
+pub async fn handle_status(
+    query: StatusQuery,
+    client_manager: Arc<ClientManager>,
+    task_health: Arc<TaskHealth>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let tag = query
+        .tag
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty());
+    let connected_clients = match &tag {
+        Some(tag) => client_manager.count_by_tag(tag).await,
+        None => client_manager.client_count().await,
+    };
+
+    let status = StatusResponse {
+        status: "running".to_string(),
+        connected_clients,
+        tag,
+        background_tasks_healthy: task_health.is_healthy(BACKGROUND_TASK_STALE_SECS).await,
+        draining: client_manager.is_draining(),
+        circuit_breaker: client_manager
+            .circuit_state()
+            .await
+            .map_or("disabled", |state| state.as_str())
+            .to_string(),
+        total_executes: client_manager.metrics().total_executes(),
+        total_broadcasts_failed: client_manager.metrics().total_broadcasts_failed(),
+        total_clients_ever_connected: client_manager.metrics().total_clients_ever_connected(),
+        uptime_seconds: client_manager.uptime_secs(),
+        timestamp: Timestamp::now(),
+    };
+
+    Ok(warp::reply::json(&status))
+}
+
+/// Handle /clients endpoint - lists connected clients with their connection and pong times
+pub async fn handle_clients(
+    client_manager: Arc<ClientManager>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let response = ClientsResponse {
+        clients: client_manager.list_clients().await,
+    };
+
+    Ok(warp::reply::json(&response))
+}
+
+/// Handle /history endpoint - lists recent broadcasts and the clients they reached
+pub async fn handle_history(
+    client_manager: Arc<ClientManager>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let response = HistoryResponse {
+        history: client_manager.history().await,
+    };
+
+    Ok(warp::reply::json(&response))
+}
+
+/// Handle /replay endpoint - sends the last script broadcast to every client to everyone again
+pub async fn handle_replay(
+    ip: Option<IpAddr>,
+    client_manager: Arc<ClientManager>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (response, status) = replay(ip, &client_manager).await;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        status,
+    ))
+}
+
+/// Replay the last script, returning the response and status for /replay
+pub async fn replay(
+    ip: Option<IpAddr>,
+    client_manager: &ClientManager,
+) -> (ExecuteResponse, StatusCode) {
+    let Some((filename, (successful, total, failed_clients, disconnected_clients))) =
+        client_manager.replay().await
+    else {
+        let response = ExecuteResponse {
+            error: Some("No script has been broadcast yet".to_string()),
+            ..Default::default()
+        };
+        return (response, StatusCode::NOT_FOUND);
+    };
+    log(&format!(
+        "Replay of '{}' requested by {}",
+        filename,
+        describe_ip(ip)
+    ));
+
+    if total == 0 {
+        let response = ExecuteResponse {
+            error: Some("No clients connected".to_string()),
+            clients_reached: Some(0),
+            total_clients: Some(0),
+            ..Default::default()
+        };
+        (response, StatusCode::SERVICE_UNAVAILABLE)
+    } else if failed_clients.is_empty() && successful > 0 {
+        let response = ExecuteResponse {
+            success: true,
+            message: Some(format!(
+                "Script '{}' replayed to all connected clients{}",
+                filename,
+                disconnected_note(&disconnected_clients)
+            )),
+            clients_reached: Some(successful),
+            total_clients: Some(total),
+            disconnected_clients: non_empty(disconnected_clients),
+            ..Default::default()
+        };
+        (response, StatusCode::OK)
+    } else {
+        let response = ExecuteResponse {
+            error: Some(format!(
+                "Replay of '{}' only reached {}/{} clients{}",
+                filename,
+                successful,
+                total,
+                disconnected_note(&disconnected_clients)
+            )),
+            clients_reached: Some(successful),
+            total_clients: Some(total),
+            failed_clients: Some(failed_clients),
+            disconnected_clients: non_empty(disconnected_clients),
+            ..Default::default()
+        };
+        (response, StatusCode::MULTI_STATUS)
+    }
+}
+
+/// Route for POST /drain, guarded by the API key since draining refuses every new executor
+pub fn drain_route(
+    client_manager: Arc<ClientManager>,
+    config: Arc<ServerConfig>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::path("drain")
+        .and(warp::post())
+        .and(require_api_key(config))
+        .and_then(move || handle_drain(client_manager.clone()))
+}
+
+/// Handle /drain endpoint - toggles whether new WebSocket connections are refused
+pub async fn handle_drain(
+    client_manager: Arc<ClientManager>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let response = DrainResponse {
+        draining: client_manager.toggle_draining(),
+    };
+
+    Ok(warp::reply::json(&response))
+}
+
+/// Route for POST /ping and /ping/{id}, guarded by the API key since it sends to the whole fleet
+pub fn ping_route(
+    client_manager: Arc<ClientManager>,
+    config: Arc<ServerConfig>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::path("ping")
+        .and(
+            warp::path::param::<usize>()
+                .map(Some)
+                .or(warp::any().map(|| None))
+                .unify(),
+        )
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(require_api_key(config))
+        .and_then(move |client_id: Option<usize>| handle_ping(client_id, client_manager.clone()))
+}
+
+/// Handle /ping endpoint - pings every client right away instead of waiting for the interval,
+/// or a single client for /ping/{id}
+pub async fn handle_ping(
+    client_id: Option<usize>,
+    client_manager: Arc<ClientManager>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (response, status) = match client_id {
+        Some(id) if !client_manager.send_ping_to(id).await => {
+            let response = PingResponse {
+                success: false,
+                clients_pinged: 0,
+                error: Some(format!("Client {} not connected", id)),
+            };
+            (response, StatusCode::NOT_FOUND)
+        }
+        Some(_) => {
+            let response = PingResponse {
+                success: true,
+                clients_pinged: 1,
+                error: None,
+            };
+            (response, StatusCode::OK)
+        }
+        None => {
+            let response = PingResponse {
+                success: true,
+                clients_pinged: client_manager.send_ping().await,
+                error: None,
+            };
+            (response, StatusCode::OK)
+        }
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        status,
+    ))
+}
+
+/// Handle /disconnect/{id} endpoint - kicks a single client, closing its connection
+pub async fn handle_disconnect(
+    client_id: usize,
+    client_manager: Arc<ClientManager>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (response, status) = if client_manager.disconnect(client_id).await {
+        let response = DisconnectResponse {
+            success: true,
+            message: Some(format!("Client {} disconnected", client_id)),
+            error: None,
+        };
+        (response, StatusCode::OK)
+    } else {
+        let response = DisconnectResponse {
+            success: false,
+            message: None,
+            error: Some(format!("Client {} not connected", client_id)),
+        };
+        (response, StatusCode::NOT_FOUND)
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        status,
+    ))
+}
+
+/// Handle /metrics endpoint - usage counters in the Prometheus text format
+pub async fn handle_metrics(
+    client_manager: Arc<ClientManager>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let body = client_manager.metrics().render_prometheus(
+        client_manager.client_count().await,
+        client_manager.uptime_secs(),
+    );
+
+    Ok(warp::reply::with_header(
+        body,
+        "content-type",
+        PROMETHEUS_CONTENT_TYPE,
+    ))
+}
+
+/// Handle /events endpoint - streams server activity as Server-Sent Events until the client
+/// goes away or the server shuts down
+pub async fn handle_events(
+    client_manager: Arc<ClientManager>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let events = client_manager.subscribe_events();
+    let stream = futures_util::stream::unfold(events, |mut events| async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    let sse = warp::sse::Event::default()
+                        .event(event.event_type.clone())
+                        .json_data(&event);
+                    return Some((sse, events));
+                }
+                Err(RecvError::Lagged(missed)) => warn(&format!(
+                    "An /events subscriber fell behind and missed {} events",
+                    missed
+                )),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(stream)))
+}
+
+/// Handle /reload endpoint - re-reads the config and swaps in the new allowed extensions and
+/// size limit, leaving connected clients alone
+pub async fn handle_reload(
+    ip: Option<IpAddr>,
+    live_config: Arc<LiveConfig>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (response, status) = match live_config.reload().await {
+        Ok(config) => {
+            log(&format!(
+                "Config reloaded by {}: allowed extensions {:?}, max script size {} bytes",
+                describe_ip(ip),
+                config.allowed_extensions,
+                config.max_script_bytes
+            ));
+            let response = ReloadResponse {
+                success: true,
+                allowed_extensions: Some(config.allowed_extensions.clone()),
+                max_script_bytes: Some(config.max_script_bytes),
+                error: None,
+            };
+            (response, StatusCode::OK)
+        }
+        Err(e) => {
+            let error = format!("{:#}", e);
+            warn(&format!(
+                "Config reload requested by {} failed: {}",
+                describe_ip(ip),
+                error
+            ));
+            let response = ReloadResponse {
+                success: false,
+                allowed_extensions: None,
+                max_script_bytes: None,
+                error: Some(error),
+            };
+            (response, StatusCode::BAD_REQUEST)
+        }
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        status,
+    ))
+}
+
+/// Handle /shutdown endpoint - stops the server the same way Ctrl+C does
+/// Refused with 403 unless an API key is configured and the request carries it
+pub async fn handle_shutdown(
+    authorized: bool,
+    ip: Option<IpAddr>,
+    shutdown: Arc<Notify>,
+    config: Arc<ServerConfig>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let error = if config.api_key.is_none() {
+        Some("Shutdown over HTTP requires --api-key to be set")
+    } else if !authorized {
+        Some("Missing or invalid API key")
+    } else {
+        None
+    };
+
+    let (response, status) = match error {
+        Some(error) => {
+            warn(&format!(
+                "Refused shutdown request from {}: {}",
+                describe_ip(ip),
+                error
+            ));
+            let response = ExecuteResponse {
+                error: Some(error.to_string()),
+                ..Default::default()
+            };
+            (response, StatusCode::FORBIDDEN)
+        }
+        None => {
+            log(&format!("Shutdown requested by {}", describe_ip(ip)));
+            shutdown.notify_one();
+            let response = ExecuteResponse {
+                success: true,
+                message: Some("Server is shutting down".to_string()),
+                ..Default::default()
+            };
+            (response, StatusCode::OK)
+        }
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        status,
+    ))
+}
+
+/// Handle /version endpoint - reports which build of the proxy is running
+pub async fn handle_version() -> Result<impl warp::Reply, warp::Rejection> {
+    let response = VersionResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_hash: option_env!("GIT_HASH").map(str::to_string),
+    };
+
+    Ok(warp::reply::json(&response))
+}
+
+/// Handle /whoami endpoint - reports how the server sees the caller
+pub async fn handle_whoami(
+    peer: Option<SocketAddr>,
+    ip: Option<IpAddr>,
+    authorized: bool,
+    config: Arc<ServerConfig>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let response = WhoamiResponse {
+        ip: ip.map(|ip| ip.to_string()),
+        peer_ip: peer.map(|addr| addr.ip().to_string()),
+        trust_forwarded: config.trust_forwarded,
+        auth_enabled: config.api_key.is_some(),
+        authorized,
+    };
+
+    Ok(warp::reply::json(&response))
 }
@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use tokio::sync::Mutex;
+
+/// Name of the background task that sends pings to clients
+pub const PING_SENDER_TASK: &str = "ping_sender";
+
+/// Name of the background task that disconnects unresponsive clients
+pub const TIMEOUT_CHECKER_TASK: &str = "timeout_checker";
+
+/// Tracks when each background task last ran so a dead task can be detected
+pub struct TaskHealth {
+    last_ran: Mutex<HashMap<&'static str, Instant>>,
+}
+
+impl TaskHealth {
+    /// Create a tracker for the given tasks, treating each as having just run
+    pub fn new(tasks: &[&'static str]) -> Self {
+        let now = Instant::now();
+        Self {
+            last_ran: Mutex::new(tasks.iter().map(|task| (*task, now)).collect()),
+        }
+    }
+
+    /// Record that a task has completed a tick
+    pub async fn beat(&self, task: &'static str) {
+        self.last_ran.lock().await.insert(task, Instant::now());
+    }
+
+    /// Check whether every tracked task has run within the given number of seconds
+    pub async fn is_healthy(&self, max_age_secs: u64) -> bool {
+        let last_ran = self.last_ran.lock().await;
+        let now = Instant::now();
+
+        last_ran
+            .values()
+            .all(|last_time| now.duration_since(*last_time).as_secs() <= max_age_secs)
+    }
+}
@@ -0,0 +1,56 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Files currently being executed, so the same file isn't read and broadcast twice at once
+#[derive(Default)]
+pub struct InFlight {
+    paths: Mutex<HashSet<PathBuf>>,
+}
+
+impl InFlight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Claim every path for the life of the returned guard
+    /// Fails with the first path another request already holds, claiming none of them
+    pub fn claim<'a>(&'a self, paths: &[String]) -> Result<InFlightGuard<'a>, String> {
+        let keys: HashSet<PathBuf> = paths.iter().map(|path| key(Path::new(path))).collect();
+        let mut in_flight = self.paths.lock().unwrap();
+
+        if let Some(busy) = paths
+            .iter()
+            .find(|path| in_flight.contains(&key(Path::new(path))))
+        {
+            return Err(busy.clone());
+        }
+        in_flight.extend(keys.iter().cloned());
+
+        Ok(InFlightGuard {
+            in_flight: self,
+            keys,
+        })
+    }
+}
+
+/// Releases its claimed paths when dropped
+pub struct InFlightGuard<'a> {
+    in_flight: &'a InFlight,
+    keys: HashSet<PathBuf>,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.in_flight.paths.lock().unwrap();
+        for key in &self.keys {
+            in_flight.remove(key);
+        }
+    }
+}
+
+/// Resolve a path so different spellings of the same file share a claim, falling back to the
+/// path as given when it can't be resolved
+fn key(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
@@ -0,0 +1,23 @@
+//! Core of the Roblox executor proxy, shared by the server binary and the integration tests
+
+pub mod auth;
+pub mod circuit_breaker;
+pub mod client_ip;
+pub mod client_manager;
+pub mod compression;
+pub mod config;
+pub mod debounce;
+pub mod frame;
+pub mod handlers;
+pub mod health;
+pub mod in_flight;
+pub mod live_config;
+pub mod log_file;
+pub mod metrics;
+pub mod minify;
+pub mod pid_file;
+pub mod rate_limit;
+pub mod timestamp;
+pub mod types;
+pub mod watch;
+pub mod webhook;
@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::sync::RwLock;
+use warp::{Filter, Rejection};
+
+use crate::config::ServerConfig;
+
+/// Rejection for request bodies over the current size limit
+#[derive(Debug)]
+pub struct BodyTooLarge;
+
+impl warp::reject::Reject for BodyTooLarge {}
+
+/// Rejection for requests without a Content-Length, e.g. chunked uploads, whose size can't be
+/// checked before reading
+#[derive(Debug)]
+pub struct LengthRequired;
+
+impl warp::reject::Reject for LengthRequired {}
+
+/// Configuration shared by the request handlers, with the settings that can change at runtime
+/// swapped in by /reload
+pub struct LiveConfig {
+    current: RwLock<Arc<ServerConfig>>,
+}
+
+impl LiveConfig {
+    pub fn new(config: ServerConfig) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(config)),
+        }
+    }
+
+    /// The configuration in effect right now
+    /// Requests keep the snapshot they started with even if a reload happens meanwhile
+    pub async fn get(&self) -> Arc<ServerConfig> {
+        self.current.read().await.clone()
+    }
+
+    /// Re-read the command line, environment variables and config file, then apply the result
+    pub async fn reload(&self) -> Result<Arc<ServerConfig>> {
+        let fresh = ServerConfig::from_args()?;
+        Ok(self.apply(fresh).await)
+    }
+
+    /// Take the allowed extensions and size limit from `fresh`, keeping every other setting
+    /// since ports, TLS and the like can't change without a restart
+    pub async fn apply(&self, fresh: ServerConfig) -> Arc<ServerConfig> {
+        let mut current = self.current.write().await;
+        let mut updated = ServerConfig::clone(&current);
+        updated.allowed_extensions = fresh.allowed_extensions;
+        updated.max_script_bytes = fresh.max_script_bytes;
+
+        let updated = Arc::new(updated);
+        *current = updated.clone();
+        updated
+    }
+}
+
+/// Filter rejecting bodies whose Content-Length is over the current size limit before they
+/// are read, like `warp::body::content_length_limit` but following reloads
+/// Requests without a Content-Length are rejected with `LengthRequired`, as warp's filter does
+pub fn body_size_limit(
+    live: Arc<LiveConfig>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<u64>("content-length")
+        .and_then(move |length: Option<u64>| {
+            let live = live.clone();
+            async move {
+                let Some(length) = length else {
+                    return Err(warp::reject::custom(LengthRequired));
+                };
+                if length <= live.get().await.max_script_bytes {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(BodyTooLarge))
+                }
+            }
+        })
+        .untuple_one()
+}
@@ -0,0 +1,65 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// Log file that log lines are appended to alongside stdout
+/// Once it would grow past `max_bytes` it is moved to `<path>.1`, replacing any older copy,
+/// and a fresh file is started
+pub struct LogFile {
+    path: PathBuf,
+    max_bytes: u64,
+    writer: BufWriter<File>,
+    /// Bytes in the current file, including whatever it held before the server started
+    written: u64,
+}
+
+impl LogFile {
+    /// Open the log file for appending, creating it if needed
+    pub fn open(path: &Path, max_bytes: u64) -> Result<Self> {
+        let (writer, written) = open_append(path)
+            .with_context(|| format!("Failed to open log file '{}'", path.display()))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            writer,
+            written,
+        })
+    }
+
+    /// Append a line, rotating first if it would take the file over its size limit
+    /// Each line is flushed straight away so nothing is lost if the server is killed
+    pub fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let length = line.len() as u64 + 1;
+        if self.written > 0 && self.written + length > self.max_bytes {
+            self.rotate()?;
+        }
+
+        writeln!(self.writer, "{}", line)?;
+        self.writer.flush()?;
+        self.written += length;
+        Ok(())
+    }
+
+    /// Path the previous file is moved to when rotating
+    pub fn rotated_path(&self) -> PathBuf {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        PathBuf::from(rotated)
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.writer.flush()?;
+        fs::rename(&self.path, self.rotated_path())?;
+        (self.writer, self.written) = open_append(&self.path)?;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> std::io::Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let written = file.metadata()?.len();
+    Ok((BufWriter::new(file), written))
+}
@@ -8,93 +8,442 @@ Example curl command (Windows CMD):
   curl -X POST http://localhost:13377/execute_file -d "C:\path\to\script.lua"
 */
 
-mod client_manager;
-mod config;
-mod handlers;
-mod types;
-
-use std::net::ToSocketAddrs;
+use std::net::{SocketAddr, ToSocketAddrs};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
 use bytes::Bytes;
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
+use warp::cors::Builder;
+use warp::filters::BoxedFilter;
 use warp::ws::Ws;
-use warp::Filter;
+use warp::{Filter, Reply};
 
-use client_manager::ClientManager;
-use config::{ServerConfig, PING_INTERVAL_SECS, PONG_TIMEOUT_SECS};
-use handlers::{handle_execute, handle_status, handle_websocket};
+use roblox_executor_proxy::auth::{authorized, handle_rejection, require_api_key};
+use roblox_executor_proxy::circuit_breaker::CircuitBreaker;
+use roblox_executor_proxy::client_ip::client_ip;
+use roblox_executor_proxy::client_manager::{self, log, ClientManager, ConnectionHooks};
+use roblox_executor_proxy::compression::gzip_if_accepted;
+use roblox_executor_proxy::config::{
+    ServerConfig, MIN_PING_INTERVAL_SECS, PING_SCHEDULER_TICK_SECS, SHUTDOWN_GRACE_SECS,
+    SINGLE_PORT_WS_PATH,
+};
+use roblox_executor_proxy::handlers::{
+    drain_route, handle_broadcast, handle_clients, handle_disconnect, handle_events,
+    handle_execute, handle_execute_code, handle_history, handle_metrics, handle_reload,
+    handle_replay, handle_run, handle_shutdown, handle_status, handle_version, handle_websocket,
+    handle_whoami, ping_route, reject_ws_upgrade,
+};
+use roblox_executor_proxy::health::{TaskHealth, PING_SENDER_TASK, TIMEOUT_CHECKER_TASK};
+use roblox_executor_proxy::live_config::{body_size_limit, LiveConfig};
+use roblox_executor_proxy::log_file::LogFile;
+use roblox_executor_proxy::pid_file::PidFile;
+use roblox_executor_proxy::rate_limit::{rate_limit, RateLimiter};
+use roblox_executor_proxy::timestamp;
+use roblox_executor_proxy::types::{ExecuteQuery, StatusQuery};
+use roblox_executor_proxy::watch::spawn_watcher;
+use roblox_executor_proxy::webhook::ResultWebhook;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let config = ServerConfig::from_args()?;
+    let config = Arc::new(ServerConfig::from_args()?);
+    client_manager::set_quiet(config.quiet);
+    client_manager::set_log_format(config.log_format);
+    timestamp::set_format(config.timestamp_format);
+    if let Some(path) = &config.log_file {
+        client_manager::set_log_file(LogFile::open(path, config.log_max_bytes)?);
+    }
+
+    // Lets /shutdown, and --on-empty exit, stop the server the same way Ctrl+C does
+    let shutdown_request = Arc::new(Notify::new());
 
     // Create client manager
-    let client_manager = Arc::new(ClientManager::new());
+    let mut client_manager = ClientManager::new()
+        .with_hooks(ConnectionHooks {
+            on_connect: config.on_connect.clone(),
+            on_disconnect: config.on_disconnect.clone(),
+        })
+        .with_ping_interval(config.ping_interval_secs)
+        .with_welcome(config.welcome_message())
+        .with_history_size(config.history_size)
+        .with_client_buffer(config.client_buffer)
+        .with_on_empty(config.on_empty, shutdown_request.clone());
+    if let Some(threshold) = config.breaker_threshold {
+        client_manager = client_manager.with_circuit_breaker(CircuitBreaker::new(
+            threshold,
+            Duration::from_secs(config.breaker_cooldown_secs),
+        ));
+    }
+    if let Some(url) = &config.result_webhook {
+        client_manager = client_manager.with_result_webhook(ResultWebhook::new(url.clone()));
+    }
+    if let Some(debounce_ms) = config.debounce_ms {
+        client_manager = client_manager.with_debounce(Duration::from_millis(debounce_ms));
+    }
+    if let Some(max_clients) = config.max_clients {
+        client_manager = client_manager.with_max_clients(max_clients);
+    }
+    if let Some(max) = config.max_concurrent_broadcasts {
+        client_manager = client_manager.with_max_concurrent_broadcasts(max);
+    }
+    if config.retry_sends {
+        client_manager = client_manager.with_send_retry();
+    }
+    if config.drop_slow_clients {
+        client_manager = client_manager.with_slow_client_drop();
+    }
+    if config.replay_last {
+        client_manager = client_manager.with_replay_last();
+    }
+    if config.broadcast_count {
+        client_manager = client_manager.with_count_broadcast();
+    }
+    if config.queue_on_empty {
+        client_manager = client_manager.with_queue(Duration::from_secs(config.queue_ttl_secs));
+    }
+    let client_manager = Arc::new(client_manager);
+
+    // Track background task liveness for /status
+    let task_health = Arc::new(TaskHealth::new(&[PING_SENDER_TASK, TIMEOUT_CHECKER_TASK]));
+
+    // Every route is mounted under --base-path, for running behind a reverse proxy
+    let base_path = config
+        .base_path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .fold(warp::any().boxed(), |filter, segment| {
+            filter.and(warp::path(segment.to_string())).boxed()
+        });
 
-    // WebSocket route
+    // WebSocket route, at the root of its own port or under /ws when sharing the HTTP port
+    let ws_path = if config.single_port {
+        base_path
+            .clone()
+            .and(warp::path(SINGLE_PORT_WS_PATH))
+            .and(warp::path::end())
+            .boxed()
+    } else {
+        base_path.clone().and(warp::path::end()).boxed()
+    };
     let client_manager_ws = client_manager.clone();
-    let ws_route = warp::path::end().and(warp::ws()).map(move |ws: Ws| {
-        let client_manager = client_manager_ws.clone();
-        ws.on_upgrade(move |socket| handle_websocket(socket, client_manager))
-    });
+    let ws_route = ws_path
+        .and(warp::ws())
+        .and(warp::addr::remote())
+        .map(move |ws: Ws, address: Option<SocketAddr>| {
+            let client_manager = client_manager_ws.clone();
+            Box::new(ws.on_upgrade(move |socket| handle_websocket(socket, address, client_manager)))
+                as Box<dyn Reply>
+        })
+        .boxed();
+
+    // Log and 404 upgrades on any other path, which usually means a client has the wrong URL
+    let ws_expected_path = if config.single_port {
+        format!("{}/{}", config.base_path, SINGLE_PORT_WS_PATH)
+    } else if config.base_path.is_empty() {
+        "/".to_string()
+    } else {
+        config.base_path.clone()
+    };
+    let ws_rejection =
+        reject_ws_upgrade(ws_expected_path).map(|reply| Box::new(reply) as Box<dyn Reply>);
+
+    // Shared by both execute routes so they count towards the same per-IP limit
+    let rate_limiter = config
+        .rate_limit
+        .map(|per_minute| Arc::new(RateLimiter::new(per_minute)));
 
     // HTTP routes
+    // Settings /reload can change, read again by each request
+    let live_config = Arc::new(LiveConfig::new(ServerConfig::clone(&config)));
+
     let client_manager_execute = client_manager.clone();
+    let live_config_execute = live_config.clone();
     let execute_route = warp::path("execute_file")
+        .and(
+            warp::path::param::<usize>()
+                .map(Some)
+                .or(warp::any().map(|| None))
+                .unify(),
+        )
+        .and(warp::path::end())
         .and(warp::post())
+        .and(require_api_key(config.clone()))
+        .and(rate_limit(rate_limiter.clone(), config.clone()))
         .and(warp::body::bytes())
-        .and_then(move |body: Bytes| {
-            let client_manager = client_manager_execute.clone();
-            let body_str = String::from_utf8_lossy(&body).to_string();
-            handle_execute(body_str, client_manager)
+        .and(warp::query::<ExecuteQuery>())
+        .and(client_ip(config.clone()))
+        .and_then(
+            move |client_id: Option<usize>, body: Bytes, query: ExecuteQuery, _peer, ip| {
+                let client_manager = client_manager_execute.clone();
+                let live_config = live_config_execute.clone();
+                async move {
+                    let config = live_config.get().await;
+                    handle_execute(body, client_id, query, ip, client_manager, config).await
+                }
+            },
+        );
+
+    let client_manager_execute_code = client_manager.clone();
+    let live_config_execute_code = live_config.clone();
+    let execute_code_route = warp::path("execute_code")
+        .and(warp::post())
+        .and(require_api_key(config.clone()))
+        .and(rate_limit(rate_limiter.clone(), config.clone()))
+        .and(body_size_limit(live_config.clone()))
+        .and(warp::body::bytes())
+        .and(warp::header::optional::<String>("x-filename"))
+        .and(warp::query::<ExecuteQuery>())
+        .and(client_ip(config.clone()))
+        .and_then(
+            move |body: Bytes, filename: Option<String>, query: ExecuteQuery, _peer, ip| {
+                let client_manager = client_manager_execute_code.clone();
+                let live_config = live_config_execute_code.clone();
+                async move {
+                    let config = live_config.get().await;
+                    handle_execute_code(body, filename, query, ip, client_manager, config).await
+                }
+            },
+        );
+
+    let client_manager_run = client_manager.clone();
+    let live_config_run = live_config.clone();
+    let run_route = warp::path!("run" / String)
+        .and(warp::post())
+        .and(require_api_key(config.clone()))
+        .and(rate_limit(rate_limiter, config.clone()))
+        .and(warp::query::<ExecuteQuery>())
+        .and(client_ip(config.clone()))
+        .and_then(move |name: String, query: ExecuteQuery, _peer, ip| {
+            let client_manager = client_manager_run.clone();
+            let live_config = live_config_run.clone();
+            async move {
+                let config = live_config.get().await;
+                handle_run(name, query, ip, client_manager, config).await
+            }
+        });
+
+    let client_manager_broadcast = client_manager.clone();
+    let broadcast_route = warp::path("broadcast")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(require_api_key(config.clone()))
+        .and(body_size_limit(live_config.clone()))
+        .and(warp::body::bytes())
+        .and(client_ip(config.clone()))
+        .and_then(move |body: Bytes, _peer, ip| {
+            let client_manager = client_manager_broadcast.clone();
+            handle_broadcast(body, ip, client_manager)
+        });
+
+    let client_manager_replay = client_manager.clone();
+    let replay_route = warp::path("replay")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(require_api_key(config.clone()))
+        .and(client_ip(config.clone()))
+        .and_then(move |_peer, ip| {
+            let client_manager = client_manager_replay.clone();
+            handle_replay(ip, client_manager)
         });
 
     let client_manager_status = client_manager.clone();
-    let status_route = warp::path("status").and(warp::get()).and_then(move || {
-        let client_manager = client_manager_status.clone();
-        handle_status(client_manager)
+    let task_health_status = task_health.clone();
+    let status_route = warp::path("status")
+        .and(warp::get())
+        .and(warp::query::<StatusQuery>())
+        .and_then(move |query: StatusQuery| {
+            let client_manager = client_manager_status.clone();
+            let task_health = task_health_status.clone();
+            handle_status(query, client_manager, task_health)
+        });
+
+    let client_manager_clients = client_manager.clone();
+    let clients_route = warp::path("clients").and(warp::get()).and_then(move || {
+        let client_manager = client_manager_clients.clone();
+        handle_clients(client_manager)
+    });
+
+    let client_manager_history = client_manager.clone();
+    let history_route = warp::path("history").and(warp::get()).and_then(move || {
+        let client_manager = client_manager_history.clone();
+        handle_history(client_manager)
     });
 
-    let http_routes = execute_route.or(status_route);
+    let client_manager_metrics = client_manager.clone();
+    let metrics_route = warp::path("metrics").and(warp::get()).and_then(move || {
+        let client_manager = client_manager_metrics.clone();
+        handle_metrics(client_manager)
+    });
 
-    // Start ping sender background task
+    let client_manager_events = client_manager.clone();
+    let events_route = warp::path("events")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then(move || {
+            let client_manager = client_manager_events.clone();
+            handle_events(client_manager)
+        });
+
+    let drain_route = drain_route(client_manager.clone(), config.clone());
+
+    let ping_route = ping_route(client_manager.clone(), config.clone());
+
+    let client_manager_disconnect = client_manager.clone();
+    let disconnect_route = warp::path!("disconnect" / usize)
+        .and(warp::post())
+        .and(require_api_key(config.clone()))
+        .and_then(move |client_id: usize| {
+            let client_manager = client_manager_disconnect.clone();
+            handle_disconnect(client_id, client_manager)
+        });
+
+    let live_config_reload = live_config.clone();
+    let reload_route = warp::path("reload")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(require_api_key(config.clone()))
+        .and(client_ip(config.clone()))
+        .and_then(move |_peer, ip| {
+            let live_config = live_config_reload.clone();
+            handle_reload(ip, live_config)
+        });
+
+    let shutdown_request_route = shutdown_request.clone();
+    let config_shutdown = config.clone();
+    let shutdown_route = warp::path("shutdown")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(authorized(config.clone()))
+        .and(client_ip(config.clone()))
+        .and_then(move |authorized, _peer, ip| {
+            let shutdown = shutdown_request_route.clone();
+            let config = config_shutdown.clone();
+            handle_shutdown(authorized, ip, shutdown, config)
+        });
+
+    let version_route = warp::path("version")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then(handle_version);
+
+    let config_whoami = config.clone();
+    let whoami_route = warp::path("whoami")
+        .and(warp::get())
+        .and(client_ip(config.clone()))
+        .and(authorized(config.clone()))
+        .and_then(move |peer, ip, authorized| {
+            let config = config_whoami.clone();
+            handle_whoami(peer, ip, authorized, config)
+        });
+
+    // Informational GET routes, gzipped with --compress-responses for clients that accept it
+    let info_routes = status_route
+        .or(clients_route)
+        .or(history_route)
+        .or(metrics_route)
+        .or(version_route)
+        .or(whoami_route);
+    let info_routes = if config.compress_responses {
+        gzip_if_accepted(info_routes)
+    } else {
+        info_routes
+            .map(|reply| Box::new(reply) as Box<dyn Reply>)
+            .boxed()
+    };
+
+    let http_routes = execute_route
+        .or(execute_code_route)
+        .or(run_route)
+        .or(broadcast_route)
+        .or(replay_route)
+        .or(info_routes)
+        .or(events_route)
+        .or(drain_route)
+        .or(ping_route)
+        .or(disconnect_route)
+        .or(reload_route)
+        .or(shutdown_route);
+    let http_routes = base_path.and(http_routes).recover(handle_rejection);
+
+    // Only send CORS headers when origins are configured, leaving responses untouched otherwise
+    let http_routes = match cors(&config) {
+        Some(cors) => http_routes
+            .with(cors)
+            .map(|reply| Box::new(reply) as Box<dyn Reply>)
+            .boxed(),
+        None => http_routes
+            .map(|reply| Box::new(reply) as Box<dyn Reply>)
+            .boxed(),
+    };
+
+    // Start ping sender background task, pinging each client on its own interval
     let client_manager_ping = client_manager.clone();
-    tokio::spawn(async move {
-        let mut interval = tokio::time::interval(Duration::from_secs(PING_INTERVAL_SECS));
+    let task_health_ping = task_health.clone();
+    let ping_task = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(PING_SCHEDULER_TICK_SECS));
         loop {
             interval.tick().await;
-            client_manager_ping.send_ping().await;
+            client_manager_ping.send_due_pings().await;
+            task_health_ping.beat(PING_SENDER_TASK).await;
         }
     });
 
     // Start timeout checker background task
     let client_manager_timeout = client_manager.clone();
-    tokio::spawn(async move {
-        let mut interval = tokio::time::interval(Duration::from_secs(PING_INTERVAL_SECS));
+    let config_timeout = config.clone();
+    let task_health_timeout = task_health.clone();
+    let timeout_task = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(MIN_PING_INTERVAL_SECS));
         loop {
             interval.tick().await;
             let timed_out = client_manager_timeout
-                .check_timeouts(PONG_TIMEOUT_SECS)
+                .check_timeouts(config_timeout.pong_timeout_secs)
                 .await;
             if !timed_out.is_empty() {
                 client_manager_timeout.disconnect_clients(timed_out).await;
             }
+            task_health_timeout.beat(TIMEOUT_CHECKER_TASK).await;
         }
     });
 
-    // Start WebSocket server
-    let ws_addr = format!("{}:{}", config.ws_host, config.ws_port);
-    let ws_socket_addr = ws_addr
-        .to_socket_addrs()
-        .context("Failed to resolve WebSocket host:port")?
-        .next()
-        .context("No addresses resolved for WebSocket host")?;
+    // Broadcast watched scripts as they change, alongside the HTTP API
+    let watch_task = config
+        .watch
+        .as_deref()
+        .map(|path| spawn_watcher(path, client_manager.clone(), live_config.clone()))
+        .transpose()?;
 
-    tokio::spawn(async move {
-        warp::serve(ws_route).run(ws_socket_addr).await;
-    });
+    // Both servers stop accepting connections once shutdown is signalled
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+    // Start WebSocket server, unless it shares the HTTP server's port
+    let (ws_task, http_routes) = if config.single_port {
+        let routes = ws_route
+            .or(http_routes)
+            .unify()
+            .or(ws_rejection)
+            .unify()
+            .boxed();
+        (None, routes)
+    } else {
+        let ws_addr = format!("{}:{}", config.ws_host, config.ws_port);
+        let ws_socket_addr = ws_addr
+            .to_socket_addrs()
+            .context("Failed to resolve WebSocket host:port")?
+            .next()
+            .context("No addresses resolved for WebSocket host")?;
+        let ws_routes = ws_route.or(ws_rejection).unify().boxed();
+        let ws_task = spawn_server(
+            ws_routes,
+            "WebSocket",
+            ws_socket_addr,
+            &config,
+            shutdown_rx.clone(),
+        )?;
+        (Some(ws_task), http_routes)
+    };
 
     // Start HTTP server
     let http_addr = format!("{}:{}", config.http_host, config.http_port);
@@ -104,10 +453,116 @@ async fn main() -> Result<()> {
         .next()
         .context("No addresses resolved for HTTP host")?;
 
+    // Bind before printing anything, so a taken port isn't mistaken for a started server
+    let http_task = spawn_server(http_routes, "HTTP", http_socket_addr, &config, shutdown_rx)?;
+
+    // Write PID file, removed again when main returns
+    let _pid_file = config
+        .pid_file
+        .as_deref()
+        .map(PidFile::create)
+        .transpose()?;
+
     // Print server info
-    config.print_info();
+    if !config.quiet {
+        config.print_info();
+    }
 
-    warp::serve(http_routes).run(http_socket_addr).await;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            result.context("Failed to listen for Ctrl+C")?;
+            log("Received Ctrl+C, shutting down");
+        }
+        _ = shutdown_request.notified() => log("Shutting down on request"),
+    }
+
+    // Stop pinging so clients aren't timed out while they close
+    ping_task.abort();
+    timeout_task.abort();
+    if let Some(watch_task) = watch_task {
+        watch_task.abort();
+    }
+
+    // Let clients know before their connections close, and give them a moment to go
+    let notified = client_manager.notify_shutdown().await;
+    log(&format!("Notified {} clients of shutdown", notified));
+    let deadline = Instant::now() + Duration::from_secs(SHUTDOWN_GRACE_SECS);
+    while client_manager.client_count().await > 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let _ = shutdown_tx.send(true);
+    let _ = http_task.await;
+    if let Some(ws_task) = ws_task {
+        let _ = ws_task.await;
+    }
 
     Ok(())
 }
+
+/// Build the CORS policy for the HTTP routes, or None when no origins are configured
+fn cors(config: &ServerConfig) -> Option<Builder> {
+    if config.cors_origins.is_empty() {
+        return None;
+    }
+
+    let cors = warp::cors().allow_methods(["GET", "POST"]).allow_headers([
+        "content-type",
+        "authorization",
+        "x-api-key",
+        "x-filename",
+    ]);
+    if config.cors_origins.iter().any(|origin| origin == "*") {
+        Some(cors.allow_any_origin())
+    } else {
+        Some(cors.allow_origins(config.cors_origins.iter().map(String::as_str)))
+    }
+}
+
+/// Start serving routes until shutdown is signalled, over TLS when a certificate and key are set
+/// Fails with a clear error, rather than panicking, when the address can't be bound
+fn spawn_server(
+    routes: BoxedFilter<(Box<dyn Reply>,)>,
+    label: &str,
+    addr: SocketAddr,
+    config: &ServerConfig,
+    shutdown_rx: watch::Receiver<bool>,
+) -> Result<JoinHandle<()>> {
+    let server = warp::serve(routes);
+    match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            let (_, server) = server
+                .tls()
+                .cert_path(cert)
+                .key_path(key)
+                .try_bind_with_graceful_shutdown(addr, shutdown_signal(shutdown_rx))
+                .map_err(|e| bind_error(e, label, addr))?;
+            Ok(tokio::spawn(server))
+        }
+        _ => {
+            let (_, server) = server
+                .try_bind_with_graceful_shutdown(addr, shutdown_signal(shutdown_rx))
+                .map_err(|e| bind_error(e, label, addr))?;
+            Ok(tokio::spawn(server))
+        }
+    }
+}
+
+/// Explain why a server couldn't be bound, calling out the common case of a port in use
+fn bind_error(error: warp::Error, label: &str, addr: SocketAddr) -> anyhow::Error {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(&error);
+    while let Some(e) = source {
+        if let Some(io_error) = e.downcast_ref::<std::io::Error>() {
+            if io_error.kind() == std::io::ErrorKind::AddrInUse {
+                return anyhow::anyhow!("{} port {} already in use", label, addr.port());
+            }
+        }
+        source = e.source();
+    }
+    anyhow::Error::new(error).context(format!("Failed to start {} server on {}", label, addr))
+}
+
+/// Resolve once shutdown has been signalled
+async fn shutdown_signal(mut shutdown_rx: watch::Receiver<bool>) {
+    let _ = shutdown_rx.wait_for(|shutdown| *shutdown).await;
+}
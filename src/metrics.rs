@@ -0,0 +1,82 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Content type of the Prometheus text exposition format
+pub const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Usage counters since startup, kept in atomics so recording never waits on a lock
+#[derive(Default)]
+pub struct Metrics {
+    executes: AtomicU64,
+    broadcasts_failed: AtomicU64,
+    clients_connected: AtomicU64,
+}
+
+impl Metrics {
+    /// Count an execute request whose script reached at least one client
+    pub fn record_execute(&self) {
+        self.executes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an execute request that missed some or all of the clients it targeted
+    pub fn record_failed_broadcast(&self) {
+        self.broadcasts_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a newly connected client
+    pub fn record_connection(&self) {
+        self.clients_connected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn total_executes(&self) -> u64 {
+        self.executes.load(Ordering::Relaxed)
+    }
+
+    pub fn total_broadcasts_failed(&self) -> u64 {
+        self.broadcasts_failed.load(Ordering::Relaxed)
+    }
+
+    pub fn total_clients_ever_connected(&self) -> u64 {
+        self.clients_connected.load(Ordering::Relaxed)
+    }
+
+    /// Render the counters, the current client count and the uptime in the Prometheus text
+    /// format for /metrics
+    pub fn render_prometheus(&self, connected_clients: usize, uptime_secs: u64) -> String {
+        let metrics = [
+            (
+                "rbxproxy_connected_clients",
+                "gauge",
+                "Executor clients connected right now",
+                connected_clients as u64,
+            ),
+            (
+                "rbxproxy_executes_total",
+                "counter",
+                "Execute requests whose script reached at least one client",
+                self.total_executes(),
+            ),
+            (
+                "rbxproxy_broadcast_failures_total",
+                "counter",
+                "Execute requests that missed some or all of their clients",
+                self.total_broadcasts_failed(),
+            ),
+            (
+                "rbxproxy_uptime_seconds",
+                "gauge",
+                "Seconds since the server started",
+                uptime_secs,
+            ),
+        ];
+
+        let mut output = String::new();
+        for (name, kind, help, value) in metrics {
+            // Writing to a String never fails
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} {}", name, kind);
+            let _ = writeln!(output, "{} {}", name, value);
+        }
+        output
+    }
+}
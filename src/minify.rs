@@ -0,0 +1,124 @@
+/// Shrink Lua source by removing comments and indentation, enabled with `--minify`
+///
+/// Strips `--` line comments and `--[[ ]]` block comments (including `--[==[ ]==]`), and the
+/// whitespace at the start and end of each line. String literals, long strings included, are
+/// copied untouched, so `--` inside them survives. Line breaks are kept, even those inside block
+/// comments, so line numbers in client errors still match the original file.
+pub fn minify_lua(source: &str) -> String {
+    let bytes = source.as_bytes();
+    let mut out = String::with_capacity(source.len());
+    let mut at_line_start = true;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\n' => {
+                end_line(&mut out, bytes, i);
+                at_line_start = true;
+                i += 1;
+            }
+            b' ' | b'\t' | b'\r' if at_line_start => i += 1,
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                let end = match long_bracket_level(bytes, i + 2) {
+                    Some(level) => long_bracket_end(bytes, i + 2, level),
+                    None => bytes[i..]
+                        .iter()
+                        .position(|&b| b == b'\n')
+                        .map_or(bytes.len(), |offset| i + offset),
+                };
+                // Keep the comment's line breaks, and a single space in place of it and the
+                // whitespace around it so the tokens either side don't run together
+                for j in i..end {
+                    if bytes[j] == b'\n' {
+                        end_line(&mut out, bytes, j);
+                        at_line_start = true;
+                    }
+                }
+                if !at_line_start {
+                    out.truncate(out.trim_end_matches([' ', '\t']).len());
+                    out.push(' ');
+                }
+                i = end
+                    + bytes[end..]
+                        .iter()
+                        .take_while(|&&b| b == b' ' || b == b'\t')
+                        .count();
+            }
+            b'[' if long_bracket_level(bytes, i).is_some() => {
+                let level = long_bracket_level(bytes, i).unwrap_or_default();
+                let end = long_bracket_end(bytes, i, level);
+                out.push_str(&source[i..end]);
+                at_line_start = false;
+                i = end;
+            }
+            quote @ (b'\'' | b'"') => {
+                let end = quoted_string_end(bytes, i, quote);
+                out.push_str(&source[i..end]);
+                at_line_start = false;
+                i = end;
+            }
+            _ => {
+                // Copy up to the next byte the scanner cares about, which keeps multi-byte
+                // characters whole since those bytes are all ASCII
+                let end = bytes[i + 1..]
+                    .iter()
+                    .position(|b| b"\n-['\"".contains(b))
+                    .map_or(bytes.len(), |offset| i + 1 + offset);
+                out.push_str(&source[i..end]);
+                at_line_start = false;
+                i = end;
+            }
+        }
+    }
+
+    out.truncate(out.trim_end_matches([' ', '\t', '\r']).len());
+    out
+}
+
+/// Finish a line at the `\n` at `newline`: drop trailing whitespace, then write the line break
+/// as the source had it
+fn end_line(out: &mut String, bytes: &[u8], newline: usize) {
+    out.truncate(out.trim_end_matches([' ', '\t', '\r']).len());
+    if newline > 0 && bytes[newline - 1] == b'\r' {
+        out.push('\r');
+    }
+    out.push('\n');
+}
+
+/// Level of the long bracket opening at `start`, e.g. 0 for `[[` and 2 for `[==[`
+fn long_bracket_level(bytes: &[u8], start: usize) -> Option<usize> {
+    if bytes.get(start) != Some(&b'[') {
+        return None;
+    }
+    let level = bytes[start + 1..]
+        .iter()
+        .take_while(|&&b| b == b'=')
+        .count();
+    (bytes.get(start + 1 + level) == Some(&b'[')).then_some(level)
+}
+
+/// End of the long bracket opening at `start`, just past its matching close, or the end of the
+/// source if it is never closed
+fn long_bracket_end(bytes: &[u8], start: usize, level: usize) -> usize {
+    let close = format!("]{}]", "=".repeat(level));
+    let body = start + level + 2;
+    bytes[body..]
+        .windows(close.len())
+        .position(|window| window == close.as_bytes())
+        .map_or(bytes.len(), |offset| body + offset + close.len())
+}
+
+/// End of the quoted string opening at `start`, just past its closing quote
+/// Escaped characters are skipped, and an unterminated string ends at the line break
+fn quoted_string_end(bytes: &[u8], start: usize, quote: u8) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'\n' => return i,
+            b if b == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use tokio::sync::Mutex;
+use warp::{Filter, Rejection};
+
+use crate::client_ip::client_ip;
+use crate::config::ServerConfig;
+
+/// Number of tracked IPs above which buckets that have refilled completely are dropped
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Rejection for callers that have used up their requests for now
+#[derive(Debug)]
+pub struct RateLimited {
+    pub retry_after_secs: u64,
+}
+
+impl warp::reject::Reject for RateLimited {}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket per caller IP, refilling continuously up to the per-minute limit
+pub struct RateLimiter {
+    per_minute: u32,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Allow each IP up to `per_minute` requests per minute, with bursts of the same size
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token for a request from `ip`
+    /// Returns the seconds until a token is available when the bucket is empty
+    pub async fn check(&self, ip: IpAddr) -> Result<(), u64> {
+        let capacity = f64::from(self.per_minute);
+        let refill_per_sec = capacity / 60.0;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().await;
+
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * refill_per_sec
+                    < capacity
+            });
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * refill_per_sec).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / refill_per_sec).ceil() as u64)
+        }
+    }
+}
+
+/// Filter rejecting callers over the rate limit, or letting everything through without one
+/// Requests whose IP can't be determined are not limited
+pub fn rate_limit(
+    limiter: Option<Arc<RateLimiter>>,
+    config: Arc<ServerConfig>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    client_ip(config)
+        .and_then(move |_peer, ip: Option<IpAddr>| {
+            let limiter = limiter.clone();
+            async move {
+                match (limiter, ip) {
+                    (Some(limiter), Some(ip)) => {
+                        limiter.check(ip).await.map_err(|retry_after_secs| {
+                            warp::reject::custom(RateLimited { retry_after_secs })
+                        })
+                    }
+                    _ => Ok(()),
+                }
+            }
+        })
+        .untuple_one()
+}
@@ -0,0 +1,49 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::config::TimestampFormat;
+
+/// Format used for every timestamp the server produces, set once at startup
+static FORMAT: AtomicU8 = AtomicU8::new(TimestampFormat::Rfc3339 as u8);
+
+/// Choose how timestamps in messages, responses and log lines are written
+pub fn set_format(format: TimestampFormat) {
+    FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+/// The configured timestamp format
+pub fn format() -> TimestampFormat {
+    match FORMAT.load(Ordering::Relaxed) {
+        x if x == TimestampFormat::EpochMs as u8 => TimestampFormat::EpochMs,
+        x if x == TimestampFormat::EpochS as u8 => TimestampFormat::EpochS,
+        _ => TimestampFormat::Rfc3339,
+    }
+}
+
+/// A moment in the configured format: an RFC 3339 string, or a number of Unix milliseconds or
+/// seconds
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Timestamp {
+    Rfc3339(String),
+    Epoch(i64),
+}
+
+impl Timestamp {
+    /// The current time
+    pub fn now() -> Self {
+        Self::from(Local::now())
+    }
+}
+
+impl From<DateTime<Local>> for Timestamp {
+    fn from(time: DateTime<Local>) -> Self {
+        match format() {
+            TimestampFormat::Rfc3339 => Self::Rfc3339(time.to_rfc3339()),
+            TimestampFormat::EpochMs => Self::Epoch(time.timestamp_millis()),
+            TimestampFormat::EpochS => Self::Epoch(time.timestamp()),
+        }
+    }
+}
@@ -1,24 +1,200 @@
 use serde::{Deserialize, Serialize};
 
-/// Generic message type for ping/pong and other simple messages
+use crate::timestamp::Timestamp;
+
+/// Ping sent to clients
+/// The server stamps pings with `ts` in Unix milliseconds, and clients that echo it back in
+/// their pong have their round-trip time recorded
 #[derive(Serialize, Deserialize)]
-pub struct SimpleMessage {
+pub struct PingMessage {
     #[serde(rename = "type")]
     pub msg_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ts: Option<i64>,
+}
+
+/// Message received from a client, told apart by its `type`
+/// Each variant is parsed strictly, so a known type with a badly typed field is an error rather
+/// than being mistaken for something else
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Answer to a ping, echoing its timestamp if the client supports round-trip times
+    Pong {
+        #[serde(default)]
+        ts: Option<i64>,
+    },
+    Hello(HelloMessage),
+    Ack(AckMessage),
+    Result(ResultMessage),
+    /// Request for the same list of peers /clients returns
+    ListClients,
+}
+
+/// Hello message a client may send after connecting
+#[derive(Deserialize)]
+pub struct HelloMessage {
+    /// Requested seconds between pings, clamped to the server's bounds
+    pub ping_interval: Option<u64>,
+    /// Human-readable name shown in /clients and log lines instead of the bare ID
+    pub name: Option<String>,
+    /// Groups the client belongs to, for sending scripts with `?tag=`
+    pub tags: Option<Vec<String>>,
+    /// Stable identity the client keeps across reconnects, shown in /clients
+    pub client_uuid: Option<String>,
+    /// Version of the message protocol the client understands
+    pub protocol: Option<u32>,
+}
+
+/// Welcome message sent to each client as it connects and again in reply to its hello, so
+/// clients can adapt to the server's settings instead of assuming them
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WelcomeMessage {
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    /// Version of the message protocol the server speaks
+    pub protocol: u32,
+    /// Seconds between pings the server will use for this client
+    pub ping_interval: u64,
+    /// Largest script the server will send, in bytes
+    pub max_script_bytes: u64,
+    /// Size of the pieces large messages are split into, if they are
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<usize>,
+    /// Whether large messages may arrive as compressed binary frames
+    pub compress: bool,
+}
+
+/// Error message sent to a client before its connection is closed
+#[derive(Serialize, Deserialize)]
+pub struct ErrorMessage {
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    pub reason: String,
 }
 
 /// Message structure for broadcasting scripts to executor clients
+///
+/// `script` is passed through byte for byte: files must be valid UTF-8 and are never re-encoded,
+/// and line endings are left as they were, so `\r\n` inside long strings survives the trip.
+/// `line_ending` and `byte_length` let clients check what they received.
+///
+/// Bytecode files are sent as `execute_binary` messages instead, with an empty `script` and the
+/// raw bytes base64-encoded in `script_b64`.
 #[derive(Serialize, Deserialize)]
 pub struct ExecuteMessage {
     #[serde(rename = "type")]
     pub msg_type: String,
+    /// Correlation ID echoed back by clients in their ack
+    pub id: u64,
     pub script: String,
+    /// Base64-encoded bytecode for `execute_binary` messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script_b64: Option<String>,
     pub filename: String,
-    pub timestamp: String,
+    /// `lf`, `crlf`, `mixed`, or `none` for single-line scripts and bytecode
+    pub line_ending: String,
+    /// Length of `script` in UTF-8 bytes, or of the decoded bytecode
+    pub byte_length: usize,
+    /// Hex SHA-256 of `script`, or of the decoded bytecode, so clients can skip a script they
+    /// just ran
+    #[serde(default)]
+    pub sha256: String,
+    pub timestamp: Timestamp,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub args: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vars: Option<serde_json::Value>,
 }
 
-/// Execute response structure for the /execute endpoint
+/// One piece of a message too large for a single frame, sent with `--chunk-size`
+///
+/// Pieces `0..total` carry consecutive slices of the original message's JSON in `data`, in
+/// order. A final piece with `seq == total` and empty `data` marks the end, after which the
+/// client joins the slices and handles the result as if it had arrived in one frame.
+#[derive(Serialize, Deserialize)]
+pub struct ChunkMessage {
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    /// ID of the message being chunked, so pieces of concurrent broadcasts can't be mixed up
+    pub id: u64,
+    pub seq: usize,
+    pub total: usize,
+    pub data: String,
+}
+
+/// Sent by a client after running an execute message
+#[derive(Deserialize)]
+pub struct AckMessage {
+    /// Correlation ID of the execute message being acknowledged
+    pub id: u64,
+    pub filename: Option<String>,
+    /// `ok` when the script ran, anything else when it failed
+    pub status: String,
+    pub error: Option<String>,
+}
+
+/// Sent by a client with the output or error of a script it ran
+#[derive(Deserialize)]
+pub struct ResultMessage {
+    pub filename: Option<String>,
+    pub output: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Script result forwarded to the result webhook, tagged with the reporting client
 #[derive(Serialize)]
+pub struct ResultReport {
+    pub client_id: usize,
+    /// Name the client registered in its hello, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_name: Option<String>,
+    pub filename: Option<String>,
+    pub output: Option<String>,
+    pub error: Option<String>,
+    pub timestamp: Timestamp,
+}
+
+/// Structured execute payload read from a .json file when JSON payloads are enabled
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JsonPayload {
+    pub script: String,
+    pub filename: Option<String>,
+    pub msg_type: Option<String>,
+    pub args: Option<serde_json::Value>,
+    pub vars: Option<serde_json::Value>,
+}
+
+/// Query parameters accepted by the /execute_file endpoint
+#[derive(Deserialize, Default, Clone)]
+pub struct ExecuteQuery {
+    /// Overrides the `type` field of the broadcast message
+    pub msg_type: Option<String>,
+    /// Fraction of connected clients to send to, chosen at random
+    pub sample: Option<f64>,
+    /// Seed for the random sample so the same clients can be chosen again
+    pub seed: Option<u64>,
+    /// Client selection strategy, currently only `lru`
+    pub target: Option<String>,
+    /// Number of clients to select with `target`
+    pub count: Option<usize>,
+    /// Wait for clients to acknowledge running the script before responding
+    pub wait_for_acks: Option<bool>,
+    /// Only send to clients that registered this tag in their hello
+    pub tag: Option<String>,
+    /// Run every check on the script without sending it
+    pub dry_run: Option<bool>,
+    /// Send the valid files of a multi-file request even when others fail validation
+    pub partial: Option<bool>,
+    /// Only send to clients speaking at least this protocol version
+    pub min_protocol: Option<u32>,
+    /// Send only to the client in this slot, counting connected clients in connection order
+    pub slot: Option<usize>,
+}
+
+/// Execute response structure for the /execute endpoint
+#[derive(Serialize, Default)]
 pub struct ExecuteResponse {
     pub success: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -29,12 +205,203 @@ pub struct ExecuteResponse {
     pub clients_reached: Option<usize>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub total_clients: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selected_clients: Option<Vec<usize>>,
+    /// Clients that acknowledged running the script, when waiting for acks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acknowledged: Option<usize>,
+    /// Clients the script could not be sent to, when some sends failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed_clients: Option<Vec<usize>>,
+    /// Clients that were connected when the broadcast started but had gone by the time it was
+    /// sent to them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disconnected_clients: Option<Vec<usize>>,
+    /// Clients left out for speaking a protocol older than `min_protocol`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped_clients: Option<Vec<usize>>,
+    /// Outcome of each file, in order, when several were sent at once
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<FileResult>>,
+}
+
+/// Outcome of one file in a multi-file /execute_file request
+#[derive(Serialize)]
+pub struct FileResult {
+    pub path: String,
+    #[serde(flatten)]
+    pub response: ExecuteResponse,
+}
+
+/// Details of a single connected client
+#[derive(Serialize)]
+pub struct ClientInfo {
+    pub id: usize,
+    /// Position in connection order among the connected clients, starting at 0
+    pub slot: usize,
+    pub connected_at: Timestamp,
+    /// Seconds since the client connected
+    pub uptime_secs: u64,
+    pub last_pong_secs_ago: u64,
+    /// Round-trip time of the latest ping, for clients that echo its timestamp
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rtt_ms: Option<u64>,
+    /// Name the client registered in its hello, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Tags the client registered in its hello
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Remote address and port the client connected from, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Stable identity the client claimed in its hello, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_uuid: Option<String>,
+    /// Protocol version the client reported in its hello, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<u32>,
+}
+
+/// Sent to a client in reply to its `list_clients` message
+#[derive(Serialize)]
+pub struct ClientListMessage {
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    pub clients: Vec<ClientInfo>,
+}
+
+/// Sent to every client when the connected count changes, with `--broadcast-count`
+#[derive(Serialize)]
+pub struct ClientCountMessage {
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    pub count: usize,
+}
+
+/// Client list response for the /clients endpoint
+#[derive(Serialize)]
+pub struct ClientsResponse {
+    pub clients: Vec<ClientInfo>,
+}
+
+/// A script broadcast kept for the /history endpoint, with a preview instead of the full source
+#[derive(Serialize, Clone)]
+pub struct HistoryEntry {
+    pub id: u64,
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    pub filename: String,
+    pub timestamp: Timestamp,
+    /// Start of the script, truncated to keep the history small
+    pub script_preview: String,
+    pub script_bytes: usize,
+    pub clients_reached: usize,
+    pub total_clients: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed_clients: Vec<usize>,
+}
+
+/// Server activity streamed to /events subscribers
+#[derive(Serialize, Clone)]
+pub struct ServerEvent {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub timestamp: Timestamp,
+    pub payload: serde_json::Value,
+}
+
+/// History response for the /history endpoint, oldest broadcast first
+#[derive(Serialize)]
+pub struct HistoryResponse {
+    pub history: Vec<HistoryEntry>,
+}
+
+/// Query parameters accepted by the /status endpoint
+#[derive(Deserialize, Default)]
+pub struct StatusQuery {
+    /// Count only clients with this tag
+    pub tag: Option<String>,
 }
 
 /// Status response structure for the /status endpoint
 #[derive(Serialize)]
 pub struct StatusResponse {
     pub status: String,
+    /// Clients connected, or only those with `tag` when filtered
     pub connected_clients: usize,
-    pub timestamp: String,
+    /// Tag the client count was filtered by
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub background_tasks_healthy: bool,
+    pub draining: bool,
+    /// Circuit breaker state: closed, open, half_open or disabled
+    pub circuit_breaker: String,
+    /// Execute requests that reached at least one client since startup
+    pub total_executes: u64,
+    /// Execute requests that missed some or all of their clients since startup
+    pub total_broadcasts_failed: u64,
+    pub total_clients_ever_connected: u64,
+    /// Seconds since the server started, which resets if it restarts
+    pub uptime_seconds: u64,
+    pub timestamp: Timestamp,
+}
+
+/// Drain response structure for the /drain endpoint
+#[derive(Serialize)]
+pub struct DrainResponse {
+    pub draining: bool,
+}
+
+/// Disconnect response structure for the /disconnect endpoint
+#[derive(Serialize)]
+pub struct DisconnectResponse {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Build information returned by the /version endpoint
+#[derive(Serialize, Deserialize)]
+pub struct VersionResponse {
+    pub version: String,
+    /// Commit the server was built from, when built from a git checkout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git_hash: Option<String>,
+}
+
+/// Ping response structure for the /ping endpoint
+#[derive(Serialize)]
+pub struct PingResponse {
+    pub success: bool,
+    pub clients_pinged: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Reload response structure for the /reload endpoint
+#[derive(Serialize)]
+pub struct ReloadResponse {
+    pub success: bool,
+    /// Settings in effect after the reload
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_extensions: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_script_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Whoami response structure for the /whoami endpoint
+#[derive(Serialize)]
+pub struct WhoamiResponse {
+    /// Caller IP as the server sees it, after applying forwarded headers if trusted
+    pub ip: Option<String>,
+    /// IP of the socket peer, which is the reverse proxy when one is in front
+    pub peer_ip: Option<String>,
+    pub trust_forwarded: bool,
+    pub auth_enabled: bool,
+    pub authorized: bool,
 }
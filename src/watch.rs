@@ -0,0 +1,100 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::client_manager::{log, warn, ClientManager};
+use crate::config::{normalize_extension, ServerConfig};
+use crate::handlers::execute_file;
+use crate::live_config::LiveConfig;
+use crate::types::ExecuteQuery;
+
+/// How long to wait for more events after a change, since editors often write a file in steps
+const SETTLE_MS: u64 = 100;
+
+/// Watch a file or directory and broadcast allowed scripts whenever they change
+/// The watcher stops when the returned task is aborted, and follows config reloads
+pub fn spawn_watcher(
+    path: &Path,
+    client_manager: Arc<ClientManager>,
+    live_config: Arc<LiveConfig>,
+) -> Result<JoinHandle<()>> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                for path in event.paths {
+                    let _ = tx.send(path);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => warn(&format!("File watch error: {}", e)),
+        })
+        .context("Failed to create file watcher")?;
+    watcher
+        .watch(path, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch '{}'", path.display()))?;
+
+    Ok(tokio::spawn(async move {
+        // Keep the watcher alive for as long as the task runs
+        let _watcher: RecommendedWatcher = watcher;
+
+        while let Some(first) = rx.recv().await {
+            tokio::time::sleep(Duration::from_millis(SETTLE_MS)).await;
+            let mut changed = BTreeSet::from([first]);
+            while let Ok(path) = rx.try_recv() {
+                changed.insert(path);
+            }
+
+            let config = live_config.get().await;
+            for path in changed {
+                if path.is_file() && is_allowed(&path, &config) {
+                    broadcast_file(&path, client_manager.clone(), config.clone()).await;
+                }
+            }
+        }
+    }))
+}
+
+fn is_allowed(path: &Path, config: &ServerConfig) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(normalize_extension)
+        .is_some_and(|extension| config.allowed_extensions.contains(&extension))
+}
+
+/// Send a changed file through the same path as /execute_file and log the outcome
+async fn broadcast_file(
+    path: &Path,
+    client_manager: Arc<ClientManager>,
+    config: Arc<ServerConfig>,
+) {
+    let path_str = path.to_string_lossy().to_string();
+    log(&format!(
+        "Watched file '{}' changed, broadcasting",
+        path_str
+    ));
+
+    let (response, status) = execute_file(
+        path_str.clone(),
+        ExecuteQuery::default(),
+        client_manager,
+        config,
+    )
+    .await;
+    match (response.message, response.error) {
+        (Some(message), _) => log(&format!("Auto-broadcast of '{}': {}", path_str, message)),
+        (None, Some(error)) => warn(&format!(
+            "Auto-broadcast of '{}' failed ({}): {}",
+            path_str,
+            status.as_u16(),
+            error
+        )),
+        (None, None) => {}
+    }
+}
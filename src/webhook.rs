@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+use crate::client_manager::warn;
+use crate::types::ResultReport;
+
+/// How long a single webhook POST may take before it is abandoned
+const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+
+/// Forwards script results reported by clients to an HTTP endpoint
+pub struct ResultWebhook {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl ResultWebhook {
+    /// Create a webhook that POSTs results as JSON to `url`
+    pub fn new(url: String) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SECS))
+            .build()
+            .unwrap_or_default();
+        Self { client, url }
+    }
+
+    /// POST a result without waiting for the response, warning if the request fails
+    pub fn send(&self, report: ResultReport) {
+        let request = self.client.post(&self.url).json(&report);
+        let url = self.url.clone();
+
+        tokio::spawn(async move {
+            match request.send().await {
+                Ok(response) if !response.status().is_success() => warn(&format!(
+                    "Result webhook '{}' returned {}",
+                    url,
+                    response.status()
+                )),
+                Ok(_) => {}
+                Err(e) => warn(&format!(
+                    "Failed to send result to webhook '{}': {}",
+                    url, e
+                )),
+            }
+        });
+    }
+}
@@ -0,0 +1,111 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use roblox_executor_proxy::auth::{handle_rejection, is_authorized};
+use roblox_executor_proxy::client_manager::ClientManager;
+use roblox_executor_proxy::config::ServerConfig;
+use roblox_executor_proxy::handlers::{drain_route, handle_shutdown, ping_route};
+use tokio::sync::Notify;
+use warp::http::StatusCode;
+use warp::{Filter, Reply};
+
+fn with_key(key: &str) -> ServerConfig {
+    ServerConfig {
+        api_key: Some(key.to_string()),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn allows_everything_without_a_configured_key() {
+    let config = ServerConfig::default();
+
+    assert!(is_authorized(None, None, &config));
+}
+
+#[test]
+fn accepts_bearer_token_or_api_key_header() {
+    let config = with_key("secret");
+
+    assert!(is_authorized(Some("Bearer secret"), None, &config));
+    assert!(is_authorized(None, Some("secret"), &config));
+}
+
+#[test]
+fn rejects_missing_or_wrong_keys() {
+    let config = with_key("secret");
+
+    assert!(!is_authorized(None, None, &config));
+    assert!(!is_authorized(Some("Bearer secreT"), None, &config));
+    assert!(!is_authorized(Some("secret"), None, &config));
+    assert!(!is_authorized(None, Some("secrets"), &config));
+}
+
+fn status(reply: Result<impl Reply, warp::Rejection>) -> StatusCode {
+    reply.unwrap().into_response().status()
+}
+
+#[tokio::test]
+async fn shutdown_needs_a_configured_and_matching_key() {
+    let shutdown = Arc::new(Notify::new());
+    let anonymous = handle_shutdown(
+        true,
+        None,
+        shutdown.clone(),
+        Arc::new(ServerConfig::default()),
+    );
+    assert_eq!(status(anonymous.await), StatusCode::FORBIDDEN);
+
+    let config = Arc::new(with_key("secret"));
+    let wrong_key = handle_shutdown(false, None, shutdown.clone(), config.clone());
+    assert_eq!(status(wrong_key.await), StatusCode::FORBIDDEN);
+
+    let notified = shutdown.notified();
+    let right_key = handle_shutdown(true, None, shutdown.clone(), config);
+    assert_eq!(status(right_key.await), StatusCode::OK);
+    tokio::time::timeout(Duration::from_secs(1), notified)
+        .await
+        .expect("shutdown should be signalled");
+}
+
+#[tokio::test]
+async fn drain_needs_the_api_key() {
+    let client_manager = Arc::new(ClientManager::new());
+    let route =
+        drain_route(client_manager.clone(), Arc::new(with_key("secret"))).recover(handle_rejection);
+    let request = || warp::test::request().method("POST").path("/drain");
+
+    let anonymous = request().reply(&route).await;
+    assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+    assert!(!client_manager.is_draining());
+
+    let authorized = request()
+        .header("authorization", "Bearer secret")
+        .reply(&route)
+        .await;
+    assert_eq!(authorized.status(), StatusCode::OK);
+    assert!(client_manager.is_draining());
+}
+
+#[tokio::test]
+async fn ping_needs_the_api_key() {
+    let client_manager = Arc::new(ClientManager::new());
+    let route = ping_route(client_manager, Arc::new(with_key("secret"))).recover(handle_rejection);
+
+    for path in ["/ping", "/ping/0"] {
+        let anonymous = warp::test::request()
+            .method("POST")
+            .path(path)
+            .reply(&route)
+            .await;
+        assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    let authorized = warp::test::request()
+        .method("POST")
+        .path("/ping")
+        .header("x-api-key", "secret")
+        .reply(&route)
+        .await;
+    assert_eq!(authorized.status(), StatusCode::OK);
+}
@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use futures_util::future::join_all;
+use roblox_executor_proxy::circuit_breaker::{BreakerState, CircuitBreaker};
+
+/// A breaker that has just opened, with no cooldown so it half-opens on the next check
+async fn opened_breaker() -> CircuitBreaker {
+    let breaker = CircuitBreaker::new(0.5, Duration::ZERO);
+    for _ in 0..3 {
+        breaker.record(0, 2).await;
+    }
+    assert_eq!(breaker.state().await, BreakerState::Open);
+    breaker
+}
+
+#[tokio::test]
+async fn lets_a_single_trial_through_while_half_open() {
+    let breaker = opened_breaker().await;
+
+    let results = join_all((0..8).map(|_| breaker.check())).await;
+    assert_eq!(breaker.state().await, BreakerState::HalfOpen);
+    let trials = results
+        .iter()
+        .filter(|result| matches!(result, Ok(Some(_))));
+    assert_eq!(trials.count(), 1);
+    let refused = results.iter().filter(|result| result.is_err());
+    assert_eq!(refused.count(), 7);
+
+    // The trial's outcome closes the breaker, after which every broadcast passes freely
+    breaker.record(2, 2).await;
+    drop(results);
+    assert_eq!(breaker.state().await, BreakerState::Closed);
+    assert!(matches!(breaker.check().await, Ok(None)));
+}
+
+#[tokio::test]
+async fn hands_the_trial_on_when_it_ends_without_an_outcome() {
+    let breaker = opened_breaker().await;
+
+    let trial = breaker.check().await.unwrap();
+    assert!(trial.is_some());
+    assert_eq!(breaker.check().await.err(), Some(1));
+
+    drop(trial);
+    assert!(matches!(breaker.check().await, Ok(Some(_))));
+    assert_eq!(breaker.state().await, BreakerState::HalfOpen);
+}
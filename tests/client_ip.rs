@@ -0,0 +1,75 @@
+use std::net::{IpAddr, SocketAddr};
+
+use roblox_executor_proxy::client_ip::resolve_client_ip;
+use roblox_executor_proxy::config::ServerConfig;
+
+fn peer(ip: &str) -> Option<SocketAddr> {
+    Some(SocketAddr::new(ip.parse().unwrap(), 50000))
+}
+
+fn ip(ip: &str) -> Option<IpAddr> {
+    Some(ip.parse().unwrap())
+}
+
+fn trusting(proxies: &[&str]) -> ServerConfig {
+    ServerConfig {
+        trust_forwarded: true,
+        trusted_proxies: proxies.iter().map(|p| p.parse().unwrap()).collect(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn ignores_forwarded_headers_unless_trusted() {
+    let config = ServerConfig::default();
+
+    let resolved = resolve_client_ip(peer("127.0.0.1"), Some("1.2.3.4"), None, &config);
+
+    assert_eq!(resolved, ip("127.0.0.1"));
+}
+
+#[test]
+fn trusts_loopback_proxy_by_default() {
+    let config = trusting(&[]);
+
+    assert_eq!(
+        resolve_client_ip(peer("127.0.0.1"), Some("1.2.3.4"), None, &config),
+        ip("1.2.3.4")
+    );
+    assert_eq!(
+        resolve_client_ip(peer("10.0.0.5"), Some("1.2.3.4"), None, &config),
+        ip("10.0.0.5")
+    );
+}
+
+#[test]
+fn ignores_headers_from_untrusted_peers() {
+    let config = trusting(&["10.0.0.1"]);
+
+    let resolved = resolve_client_ip(peer("10.0.0.9"), Some("1.2.3.4"), Some("5.6.7.8"), &config);
+
+    assert_eq!(resolved, ip("10.0.0.9"));
+}
+
+#[test]
+fn skips_trusted_hops_in_forwarded_chain() {
+    let config = trusting(&["10.0.0.1", "10.0.0.2"]);
+
+    let resolved = resolve_client_ip(
+        peer("10.0.0.1"),
+        Some("6.6.6.6, 1.2.3.4, 10.0.0.2"),
+        None,
+        &config,
+    );
+
+    assert_eq!(resolved, ip("1.2.3.4"));
+}
+
+#[test]
+fn falls_back_to_real_ip_header() {
+    let config = trusting(&["10.0.0.1"]);
+
+    let resolved = resolve_client_ip(peer("10.0.0.1"), None, Some("1.2.3.4"), &config);
+
+    assert_eq!(resolved, ip("1.2.3.4"));
+}
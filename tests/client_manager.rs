@@ -0,0 +1,484 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use roblox_executor_proxy::client_manager::{
+    ClientManager, ConnectionHooks, CLIENT_CONNECTED_EVENT, CLIENT_DISCONNECTED_EVENT,
+    CLIENT_TIMEOUT_EVENT, KICKED_CLOSE_REASON, PING_TIMEOUT_CLOSE_REASON, REPLACED_CLOSE_REASON,
+};
+use roblox_executor_proxy::config::{OnEmpty, ServerConfig, PROTOCOL_VERSION};
+use roblox_executor_proxy::types::{PingMessage, ResultMessage, WelcomeMessage};
+use roblox_executor_proxy::webhook::ResultWebhook;
+use tokio::sync::{mpsc, Notify};
+use warp::ws::Message;
+use warp::Filter;
+
+#[tokio::test]
+async fn lists_registered_client_names() {
+    let client_manager = ClientManager::new();
+    let (named_tx, _named_rx) = mpsc::channel(16);
+    let (unnamed_tx, _unnamed_rx) = mpsc::channel(16);
+    let named = client_manager.register(named_tx).await;
+    let unnamed = client_manager.register(unnamed_tx).await;
+
+    assert!(client_manager.set_name(named, "  AltAccount3 ").await);
+    assert!(!client_manager.set_name(unnamed, "   ").await);
+
+    let clients = client_manager.list_clients().await;
+    assert_eq!(clients[0].name.as_deref(), Some("AltAccount3"));
+    assert_eq!(clients[1].name, None);
+    assert_eq!(
+        client_manager.label(named).await,
+        format!("AltAccount3 ({})", named)
+    );
+    assert_eq!(client_manager.label(unnamed).await, unnamed.to_string());
+}
+
+#[tokio::test]
+async fn records_round_trip_time_from_echoed_pings() {
+    let client_manager = ClientManager::new();
+    let (echoing_tx, mut echoing_rx) = mpsc::channel(16);
+    let (silent_tx, _silent_rx) = mpsc::channel(16);
+    let echoing = client_manager.register(echoing_tx).await;
+    let silent = client_manager.register(silent_tx).await;
+
+    assert_eq!(client_manager.send_ping().await, 2);
+    let ping: PingMessage =
+        serde_json::from_str(echoing_rx.try_recv().unwrap().to_str().unwrap()).unwrap();
+    assert_eq!(ping.msg_type, "ping");
+    client_manager.update_pong(echoing, ping.ts).await;
+    client_manager.update_pong(silent, None).await;
+
+    let clients = client_manager.list_clients().await;
+    assert!(clients[0].rtt_ms.is_some_and(|rtt| rtt < 1000));
+    assert_eq!(clients[1].rtt_ms, None);
+
+    // Timestamps from the future are ignored rather than recorded
+    client_manager
+        .update_pong(silent, ping.ts.map(|ts| ts + 60_000))
+        .await;
+    assert_eq!(client_manager.list_clients().await[1].rtt_ms, None);
+}
+
+#[tokio::test]
+async fn pings_a_single_client_on_demand() {
+    let client_manager = ClientManager::new();
+    let (first_tx, mut first_rx) = mpsc::channel(16);
+    let (second_tx, mut second_rx) = mpsc::channel(16);
+    let first = client_manager.register(first_tx).await;
+    client_manager.register(second_tx).await;
+
+    assert!(client_manager.send_ping_to(first).await);
+    assert!(first_rx.try_recv().is_ok());
+    assert!(second_rx.try_recv().is_err());
+    assert!(!client_manager.send_ping_to(99).await);
+}
+
+#[tokio::test]
+async fn keeps_queued_messages_a_client_has_no_room_for() {
+    let client_manager = ClientManager::new().with_queue(Duration::from_secs(60));
+    for text in ["first", "second", "third"] {
+        assert!(
+            client_manager
+                .queue_message(vec![Message::text(text)])
+                .await
+        );
+    }
+
+    let (small_tx, mut small_rx) = mpsc::channel(2);
+    client_manager.register(small_tx).await;
+    assert_eq!(small_rx.try_recv().unwrap().to_str().unwrap(), "first");
+    assert_eq!(small_rx.try_recv().unwrap().to_str().unwrap(), "second");
+    assert!(small_rx.try_recv().is_err());
+
+    let (next_tx, mut next_rx) = mpsc::channel(16);
+    client_manager.register(next_tx).await;
+    assert_eq!(next_rx.try_recv().unwrap().to_str().unwrap(), "third");
+    assert!(next_rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn welcomes_clients_before_delivering_queued_messages() {
+    let config = ServerConfig {
+        max_script_bytes: 2048,
+        ..Default::default()
+    };
+    let client_manager = ClientManager::new()
+        .with_queue(Duration::from_secs(60))
+        .with_welcome(config.welcome_message());
+    assert!(
+        client_manager
+            .queue_message(vec![Message::text("queued")])
+            .await
+    );
+
+    let (tx, mut rx) = mpsc::channel(16);
+    client_manager.register(tx).await;
+
+    let welcome: WelcomeMessage =
+        serde_json::from_str(rx.try_recv().unwrap().to_str().unwrap()).unwrap();
+    assert_eq!(welcome.msg_type, "welcome");
+    assert_eq!(welcome.protocol, PROTOCOL_VERSION);
+    assert_eq!(welcome.max_script_bytes, 2048);
+    assert_eq!(rx.try_recv().unwrap().to_str().unwrap(), "queued");
+
+    // Hello replies carry the same settings with the client's own ping interval
+    let reply = client_manager.welcome(60);
+    assert_eq!((reply.ping_interval, reply.max_script_bytes), (60, 2048));
+}
+
+#[tokio::test]
+async fn restores_uuid_metadata_on_reconnect() {
+    let client_manager = ClientManager::new();
+    let (first_tx, _first_rx) = mpsc::channel(16);
+    let first = client_manager.register(first_tx).await;
+    assert!(client_manager.claim_uuid(first, "rig-7").await);
+    client_manager.set_name(first, "AltAccount3").await;
+    client_manager
+        .set_tags(first, &["farming".to_string()])
+        .await;
+    client_manager.unregister(first).await;
+
+    let (second_tx, _second_rx) = mpsc::channel(16);
+    let second = client_manager.register(second_tx).await;
+    assert!(client_manager.claim_uuid(second, " rig-7 ").await);
+    assert!(!client_manager.claim_uuid(second, "  ").await);
+
+    let clients = client_manager.list_clients().await;
+    assert_eq!(clients.len(), 1);
+    assert_eq!(clients[0].id, second);
+    assert_eq!(clients[0].client_uuid.as_deref(), Some("rig-7"));
+    assert_eq!(clients[0].name.as_deref(), Some("AltAccount3"));
+    assert_eq!(clients[0].tags, ["farming"]);
+}
+
+#[tokio::test]
+async fn newer_connection_replaces_one_with_the_same_uuid() {
+    let client_manager = ClientManager::new();
+    let (older_tx, mut older_rx) = mpsc::channel(16);
+    let (newer_tx, _newer_rx) = mpsc::channel(16);
+    let older = client_manager.register(older_tx).await;
+    let newer = client_manager.register(newer_tx).await;
+
+    client_manager.claim_uuid(older, "rig-7").await;
+    client_manager.set_name(older, "AltAccount3").await;
+    client_manager.claim_uuid(newer, "rig-7").await;
+
+    assert_eq!(client_manager.client_ids().await, [newer]);
+    assert_eq!(
+        client_manager.label(newer).await,
+        format!("AltAccount3 ({})", newer)
+    );
+    let close = older_rx.recv().await.unwrap();
+    assert_eq!(close.close_frame(), Some((1000, REPLACED_CLOSE_REASON)));
+}
+
+#[tokio::test]
+async fn lists_client_addresses_when_known() {
+    let client_manager = ClientManager::new();
+    let (remote_tx, _remote_rx) = mpsc::channel(16);
+    let (unknown_tx, _unknown_rx) = mpsc::channel(16);
+
+    client_manager
+        .try_register(remote_tx, Some("192.168.1.20:50312".parse().unwrap()))
+        .await
+        .unwrap();
+    client_manager.register(unknown_tx).await;
+
+    let clients = client_manager.list_clients().await;
+    assert_eq!(clients[0].address.as_deref(), Some("192.168.1.20:50312"));
+    assert_eq!(clients[1].address, None);
+}
+
+#[tokio::test]
+async fn counts_clients_by_tag() {
+    let client_manager = ClientManager::new();
+    let (farming_tx, _farming_rx) = mpsc::channel(16);
+    let (other_tx, _other_rx) = mpsc::channel(16);
+    let farming = client_manager.register(farming_tx).await;
+    let other = client_manager.register(other_tx).await;
+
+    client_manager
+        .set_tags(farming, &["farming".to_string()])
+        .await;
+    client_manager
+        .set_tags(other, &["trading".to_string()])
+        .await;
+
+    assert_eq!(client_manager.count_by_tag("farming").await, 1);
+    assert_eq!(client_manager.count_by_tag("idle").await, 0);
+    assert_eq!(client_manager.client_count().await, 2);
+}
+
+#[tokio::test]
+async fn disconnect_closes_and_drops_the_client_sender() {
+    let client_manager = ClientManager::new();
+    let (tx, mut rx) = mpsc::channel(16);
+    let id = client_manager.register(tx).await;
+
+    assert!(client_manager.disconnect(id).await);
+    let close = rx.recv().await.unwrap();
+    assert_eq!(close.close_frame(), Some((1000, KICKED_CLOSE_REASON)));
+    assert!(rx.recv().await.is_none());
+    assert_eq!(client_manager.client_count().await, 0);
+    assert!(!client_manager.disconnect(id).await);
+}
+
+#[tokio::test]
+async fn timed_out_clients_get_a_close_reason() {
+    let client_manager = ClientManager::new();
+    let (tx, mut rx) = mpsc::channel(16);
+    let id = client_manager.register(tx).await;
+
+    client_manager.disconnect_clients(vec![id]).await;
+    let close = rx.recv().await.unwrap();
+    assert_eq!(close.close_frame(), Some((1000, PING_TIMEOUT_CLOSE_REASON)));
+    assert!(rx.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn publishes_connection_events_until_shutdown() {
+    let client_manager = ClientManager::new();
+    let mut events = client_manager.subscribe_events();
+    let (tx, _rx) = mpsc::channel(16);
+    let id = client_manager.register(tx).await;
+    client_manager.disconnect_clients(vec![id]).await;
+
+    let connected = events.recv().await.unwrap();
+    assert_eq!(connected.event_type, CLIENT_CONNECTED_EVENT);
+    assert_eq!(connected.payload["client_id"], id);
+    assert_eq!(connected.payload["total_clients"], 1);
+
+    let disconnected = events.recv().await.unwrap();
+    assert_eq!(disconnected.event_type, CLIENT_DISCONNECTED_EVENT);
+    assert_eq!(disconnected.payload["reason"], PING_TIMEOUT_CLOSE_REASON);
+    assert_eq!(disconnected.payload["total_clients"], 0);
+    assert_eq!(
+        events.recv().await.unwrap().event_type,
+        CLIENT_TIMEOUT_EVENT
+    );
+
+    client_manager.notify_shutdown().await;
+    assert!(events.recv().await.is_err());
+}
+
+#[tokio::test]
+async fn broadcasts_count_changes_when_enabled() {
+    let client_manager = ClientManager::new().with_count_broadcast();
+    let (first_tx, mut first_rx) = mpsc::channel(16);
+    let (second_tx, _second_rx) = mpsc::channel(16);
+
+    client_manager.register(first_tx).await;
+    let second = client_manager.register(second_tx).await;
+    client_manager.unregister(second).await;
+
+    let counts: Vec<String> = std::iter::from_fn(|| first_rx.try_recv().ok())
+        .map(|message| message.to_str().unwrap().to_string())
+        .collect();
+    assert_eq!(
+        counts,
+        [
+            r#"{"type":"client_count","count":1}"#,
+            r#"{"type":"client_count","count":2}"#,
+            r#"{"type":"client_count","count":1}"#,
+        ]
+    );
+}
+
+#[tokio::test]
+async fn retries_failed_sends_before_removing_clients() {
+    let client_manager = Arc::new(ClientManager::new().with_send_retry());
+    let (healthy_tx, mut healthy_rx) = mpsc::channel(16);
+    let (dead_tx, dead_rx) = mpsc::channel(16);
+    client_manager.register(healthy_tx).await;
+    let dead = client_manager.register(dead_tx).await;
+    drop(dead_rx);
+
+    let broadcast = tokio::spawn({
+        let client_manager = client_manager.clone();
+        async move { client_manager.broadcast("hello").await }
+    });
+
+    // The unreachable client is kept while its send is retried
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(client_manager.client_count().await, 2);
+
+    let (successful, total, failed, disconnected) = broadcast.await.unwrap();
+    assert_eq!((successful, total), (1, 2));
+    assert!(failed.is_empty());
+    assert_eq!(disconnected, [dead]);
+    assert_eq!(client_manager.client_count().await, 1);
+    assert_eq!(healthy_rx.recv().await.unwrap().to_str().unwrap(), "hello");
+}
+
+#[tokio::test]
+async fn registers_and_unregisters_clients_while_a_broadcast_is_in_flight() {
+    let client_manager = Arc::new(ClientManager::new().with_send_retry());
+    let (dead_tx, dead_rx) = mpsc::channel(16);
+    let (leaving_tx, _leaving_rx) = mpsc::channel(16);
+    client_manager.register(dead_tx).await;
+    let leaving = client_manager.register(leaving_tx).await;
+    drop(dead_rx);
+
+    // The retry keeps this broadcast going for a while after its first sends
+    let broadcast = tokio::spawn({
+        let client_manager = client_manager.clone();
+        async move { client_manager.broadcast("hello").await }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!broadcast.is_finished());
+
+    let (joining_tx, mut joining_rx) = mpsc::channel(16);
+    let registered = tokio::time::timeout(
+        Duration::from_millis(20),
+        client_manager.register(joining_tx),
+    )
+    .await;
+    assert!(registered.is_ok(), "register waited for the broadcast");
+    tokio::time::timeout(
+        Duration::from_millis(20),
+        client_manager.unregister(leaving),
+    )
+    .await
+    .expect("unregister waited for the broadcast");
+
+    // The new client wasn't in the broadcast's snapshot, so it isn't sent the message
+    let (_, total, _, _) = broadcast.await.unwrap();
+    assert_eq!(total, 2);
+    assert!(joining_rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn reports_slow_consumers_and_drops_them_when_asked() {
+    for drop_slow in [false, true] {
+        let mut client_manager = ClientManager::new();
+        if drop_slow {
+            client_manager = client_manager.with_slow_client_drop();
+        }
+        let (reading_tx, mut reading_rx) = mpsc::channel(1);
+        let (wedged_tx, _wedged_rx) = mpsc::channel(1);
+        client_manager.register(reading_tx).await;
+        let wedged = client_manager.register(wedged_tx).await;
+
+        assert_eq!(
+            client_manager.broadcast("first").await,
+            (2, 2, vec![], vec![])
+        );
+        reading_rx.recv().await.unwrap();
+
+        let (successful, total, failed, disconnected) = client_manager.broadcast("second").await;
+        assert_eq!((successful, total), (1, 2));
+        assert_eq!(failed, [wedged]);
+        assert!(disconnected.is_empty());
+        let expected_clients = if drop_slow { 1 } else { 2 };
+        assert_eq!(client_manager.client_count().await, expected_clients);
+    }
+}
+
+#[tokio::test]
+async fn refuses_clients_past_the_limit_under_concurrent_connects() {
+    let client_manager = Arc::new(ClientManager::new().with_max_clients(3));
+
+    let attempts = (0..10).map(|_| {
+        let client_manager = client_manager.clone();
+        tokio::spawn(async move {
+            let (tx, rx) = mpsc::channel(16);
+            (client_manager.try_register(tx, None).await, rx)
+        })
+    });
+    let mut accepted = 0;
+    for attempt in attempts {
+        match attempt.await.unwrap().0 {
+            Ok(_) => accepted += 1,
+            Err(count) => assert_eq!(count, 3),
+        }
+    }
+
+    assert_eq!(accepted, 3);
+    assert_eq!(client_manager.client_count().await, 3);
+}
+
+#[tokio::test]
+async fn forwards_results_to_the_webhook() {
+    let (hook_tx, mut hook_rx) = mpsc::unbounded_channel();
+    let sink = warp::post()
+        .and(warp::body::json())
+        .map(move |report: serde_json::Value| {
+            let _ = hook_tx.send(report);
+            warp::reply()
+        });
+    let (addr, server) = warp::serve(sink).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    let client_manager = ClientManager::new()
+        .with_result_webhook(ResultWebhook::new(format!("http://{}/results", addr)));
+    let (tx, _rx) = mpsc::channel(16);
+    let id = client_manager.register(tx).await;
+    client_manager
+        .report_result(
+            id,
+            ResultMessage {
+                filename: Some("x.lua".to_string()),
+                output: Some("hello".to_string()),
+                error: None,
+            },
+        )
+        .await;
+
+    let report = tokio::time::timeout(Duration::from_secs(5), hook_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(report["client_id"], id);
+    assert_eq!(report["filename"], "x.lua");
+    assert_eq!(report["output"], "hello");
+    assert!(report["error"].is_null());
+}
+
+#[tokio::test]
+async fn requests_shutdown_once_the_last_client_leaves() {
+    let shutdown = Arc::new(Notify::new());
+    let client_manager = ClientManager::new().with_on_empty(OnEmpty::Exit, shutdown.clone());
+    let (tx, _first_rx) = mpsc::channel(16);
+    let first = client_manager.register(tx).await;
+    let (tx, _second_rx) = mpsc::channel(16);
+    let second = client_manager.register(tx).await;
+
+    client_manager.unregister(first).await;
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), shutdown.notified())
+            .await
+            .is_err()
+    );
+
+    client_manager.disconnect_clients(vec![second]).await;
+    tokio::time::timeout(Duration::from_secs(1), shutdown.notified())
+        .await
+        .expect("shutdown should be requested once no clients are left");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn passes_the_client_id_and_name_to_the_disconnect_hook() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("hook output.txt");
+    let client_manager = ClientManager::new().with_hooks(ConnectionHooks {
+        on_connect: None,
+        on_disconnect: Some(format!(r#"sh -c 'echo "$0 $1" > "{}"'"#, output.display())),
+    });
+    let (tx, _rx) = mpsc::channel(16);
+    let id = client_manager.register(tx).await;
+    client_manager.set_name(id, "AltAccount3").await;
+
+    client_manager.unregister(id).await;
+
+    for _ in 0..50 {
+        // The file exists as soon as the shell opens it, which may be before the echo
+        let contents = std::fs::read_to_string(&output).unwrap_or_default();
+        if !contents.is_empty() {
+            assert_eq!(contents.trim(), format!("{} AltAccount3", id));
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("the disconnect hook never ran");
+}
@@ -0,0 +1,29 @@
+use roblox_executor_proxy::compression::{accepts_gzip, gzip_if_accepted};
+use warp::Filter;
+
+#[test]
+fn parses_accept_encoding() {
+    assert!(accepts_gzip("gzip"));
+    assert!(accepts_gzip("deflate, GZIP;q=0.8, br"));
+    assert!(accepts_gzip("*"));
+    assert!(!accepts_gzip("deflate, br"));
+    assert!(!accepts_gzip("gzip;q=0"));
+    assert!(!accepts_gzip(""));
+}
+
+#[tokio::test]
+async fn compresses_only_when_accepted() {
+    let routes = gzip_if_accepted(warp::get().map(|| "x".repeat(4096)));
+
+    let compressed = warp::test::request()
+        .header("accept-encoding", "gzip, deflate")
+        .reply(&routes)
+        .await;
+    assert_eq!(compressed.headers()["content-encoding"], "gzip");
+    assert!(compressed.body().len() < 4096);
+
+    let plain = warp::test::request().reply(&routes).await;
+    assert!(plain.headers().get("content-encoding").is_none());
+    assert_eq!(plain.body().len(), 4096);
+    assert_eq!(plain.headers()["vary"], "accept-encoding");
+}
@@ -0,0 +1,156 @@
+use std::fs;
+use std::net::IpAddr;
+
+use roblox_executor_proxy::config::{normalize_base_path, split_command, LogFormat, ServerConfig};
+
+#[test]
+fn loads_file_values_over_defaults() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("proxy.toml");
+    fs::write(
+        &path,
+        r#"
+http_port = 8080
+api_key = "secret"
+trusted_proxies = ["10.0.0.1"]
+log_format = "json"
+allowed_extensions = ["GLUA", ".script"]
+"#,
+    )
+    .unwrap();
+
+    let config = ServerConfig::from_file(&path).unwrap();
+
+    assert_eq!(config.http_port, 8080);
+    assert_eq!(config.api_key.as_deref(), Some("secret"));
+    assert_eq!(
+        config.trusted_proxies,
+        vec!["10.0.0.1".parse::<IpAddr>().unwrap()]
+    );
+    assert_eq!(config.log_format, LogFormat::Json);
+    assert_eq!(config.allowed_extensions, vec![".glua", ".script"]);
+    assert_eq!(config.ws_port, ServerConfig::default().ws_port);
+}
+
+#[test]
+fn rejects_unknown_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("proxy.toml");
+    fs::write(&path, "http_prot = 8080\n").unwrap();
+
+    assert!(ServerConfig::from_file(&path).is_err());
+}
+
+#[test]
+fn environment_variables_fall_between_flags_and_defaults() {
+    let env = |name: &str| match name {
+        "RBXPROXY_HTTP_PORT" => Some("8080".to_string()),
+        "RBXPROXY_WS_PORT" => Some("8081".to_string()),
+        "RBXPROXY_API_KEY" => Some("from-env".to_string()),
+        "RBXPROXY_QUIET" => Some("true".to_string()),
+        "RBXPROXY_COMPRESS" => Some("0".to_string()),
+        "RBXPROXY_ALLOWED_EXT" => Some(".lua,.luau".to_string()),
+        _ => None,
+    };
+    let args: Vec<String> = ["proxy", "--ws-port", "9000", "--allowed-ext", "txt"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+
+    let config = ServerConfig::parse(&args, env).unwrap();
+
+    assert_eq!(config.http_port, 8080);
+    assert_eq!(config.ws_port, 9000);
+    assert_eq!(config.api_key.as_deref(), Some("from-env"));
+    assert!(config.quiet);
+    assert!(!config.compress);
+    assert_eq!(config.allowed_extensions, vec![".txt".to_string()]);
+    assert_eq!(
+        config.execute_timeout_secs,
+        ServerConfig::default().execute_timeout_secs
+    );
+}
+
+#[test]
+fn rejects_invalid_environment_switches() {
+    let env = |name: &str| (name == "RBXPROXY_QUIET").then(|| "sometimes".to_string());
+
+    assert!(ServerConfig::parse(&["proxy".to_string()], env).is_err());
+}
+
+#[test]
+fn normalizes_base_paths() {
+    assert_eq!(normalize_base_path("rbxproxy/"), "/rbxproxy");
+    assert_eq!(normalize_base_path("//a//b/"), "/a/b");
+    assert_eq!(normalize_base_path("/"), "");
+    assert_eq!(normalize_base_path(""), "");
+}
+
+#[test]
+fn separate_hosts_override_the_shared_one() {
+    let args: Vec<String> = [
+        "proxy",
+        "--ws-host",
+        "0.0.0.0",
+        "--host",
+        "localhost",
+        "--http-host",
+        "127.0.0.1",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect();
+
+    let config = ServerConfig::parse(&args, |_| None).unwrap();
+
+    assert_eq!(config.http_host, "127.0.0.1");
+    assert_eq!(config.ws_host, "0.0.0.0");
+
+    let args: Vec<String> = ["proxy", "--ws-host", "not a host"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    assert!(ServerConfig::parse(&args, |_| None).is_err());
+}
+
+#[test]
+fn ack_timeout_must_end_before_the_execute_timeout() {
+    let parse = |ack: &str| {
+        let args: Vec<String> = [
+            "proxy",
+            "--execute-timeout-secs",
+            "5",
+            "--ack-timeout-secs",
+            ack,
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        ServerConfig::parse(&args, |_| None)
+    };
+
+    assert!(parse("4").is_ok());
+    assert!(parse("5").is_err());
+    assert!(parse("6").is_err());
+}
+
+#[test]
+fn splits_hook_commands_keeping_quoted_arguments_together() {
+    assert_eq!(
+        split_command(r#""/opt/my tools/notify.sh" --tag 'a b'  C:\tools\log.exe"#).unwrap(),
+        [
+            "/opt/my tools/notify.sh",
+            "--tag",
+            "a b",
+            r"C:\tools\log.exe"
+        ]
+    );
+    assert_eq!(split_command("  ").unwrap(), Vec::<String>::new());
+    assert!(split_command(r#""/opt/my tools/notify.sh"#).is_none());
+
+    let args: Vec<String> = ["proxy", "--on-disconnect", "'notify.sh"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    assert!(ServerConfig::parse(&args, |_| None).is_err());
+}
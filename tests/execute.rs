@@ -0,0 +1,1344 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use roblox_executor_proxy::client_manager::ClientManager;
+use roblox_executor_proxy::config::ServerConfig;
+use roblox_executor_proxy::handlers::{
+    broadcast_control, execute_code, execute_file, execute_file_on, handle_execute_code,
+    parse_file_list, replay, run_library_script,
+};
+use roblox_executor_proxy::types::{ExecuteMessage, ExecuteQuery};
+use tokio::sync::mpsc::{self, Receiver};
+use warp::http::StatusCode;
+use warp::ws::Message;
+
+/// Register a fake client and return the receiving end of its channel
+async fn fake_client(client_manager: &ClientManager) -> Receiver<Message> {
+    let (tx, rx) = mpsc::channel(16);
+    client_manager.register(tx).await;
+    rx
+}
+
+/// Take the next message a fake client received and parse it as an execute message
+fn received_message(rx: &mut Receiver<Message>) -> ExecuteMessage {
+    let message = rx
+        .try_recv()
+        .expect("client should have received a message");
+    serde_json::from_str(message.to_str().unwrap()).unwrap()
+}
+
+fn write_script(dir: &Path, name: &str, contents: &str) -> String {
+    let path = dir.join(name);
+    fs::write(&path, contents).unwrap();
+    path.to_string_lossy().to_string()
+}
+
+#[tokio::test]
+async fn broadcasts_script_to_all_clients() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "hello.lua", "print('hello')");
+    let client_manager = Arc::new(ClientManager::new());
+    let mut first = fake_client(&client_manager).await;
+    let mut second = fake_client(&client_manager).await;
+
+    let (response, status) = execute_file(
+        path,
+        ExecuteQuery::default(),
+        client_manager,
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(response.success);
+    assert_eq!(response.clients_reached, Some(2));
+    assert_eq!(response.total_clients, Some(2));
+    for rx in [&mut first, &mut second] {
+        let message = received_message(rx);
+        assert_eq!(message.msg_type, "execute");
+        assert_eq!(message.script, "print('hello')");
+        assert_eq!(message.filename, "hello.lua");
+        assert_eq!(
+            message.sha256,
+            "96f43d529af3430cb6b0e2c02f6b38ef1a121e8a31d2d09a3ebb716f2f35c9de"
+        );
+    }
+}
+
+#[tokio::test]
+async fn reports_partial_delivery_when_a_client_is_wedged() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "hello.lua", "print('hello')");
+    let client_manager = Arc::new(ClientManager::new().with_slow_client_drop());
+    let mut alive = fake_client(&client_manager).await;
+    let (tx, _rx) = mpsc::channel(1);
+    tx.try_send(Message::text("unread")).unwrap();
+    let wedged = client_manager.register(tx).await;
+
+    let (response, status) = execute_file(
+        path,
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::MULTI_STATUS);
+    assert!(!response.success);
+    assert_eq!(response.clients_reached, Some(1));
+    assert_eq!(response.total_clients, Some(2));
+    assert_eq!(response.failed_clients, Some(vec![wedged]));
+    assert_eq!(response.disconnected_clients, None);
+    assert_eq!(received_message(&mut alive).script, "print('hello')");
+    assert_eq!(client_manager.client_count().await, 1);
+
+    let metrics = client_manager.metrics();
+    assert_eq!(metrics.total_executes(), 1);
+    assert_eq!(metrics.total_broadcasts_failed(), 1);
+    assert_eq!(metrics.total_clients_ever_connected(), 2);
+}
+
+#[tokio::test]
+async fn reports_clients_that_disconnect_during_the_broadcast() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "hello.lua", "print('hello')");
+    let client_manager = Arc::new(ClientManager::new());
+    let mut alive = fake_client(&client_manager).await;
+    let (tx, rx) = mpsc::channel(16);
+    let gone = client_manager.register(tx).await;
+    drop(rx);
+
+    let (response, status) = execute_file(
+        path,
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+
+    // Every client still connected got the script, so this isn't a partial failure
+    assert_eq!(status, StatusCode::OK);
+    assert!(response.success);
+    assert_eq!(
+        response.message.as_deref(),
+        Some("Script 'hello.lua' sent to all connected clients (1 client disconnected during broadcast)")
+    );
+    assert_eq!(response.clients_reached, Some(1));
+    assert_eq!(response.total_clients, Some(2));
+    assert_eq!(response.failed_clients, None);
+    assert_eq!(response.disconnected_clients, Some(vec![gone]));
+    assert_eq!(received_message(&mut alive).script, "print('hello')");
+    assert_eq!(client_manager.client_count().await, 1);
+    assert_eq!(client_manager.metrics().total_broadcasts_failed(), 0);
+}
+
+#[tokio::test]
+async fn returns_unavailable_without_clients() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "hello.lua", "print('hello')");
+
+    let (response, status) = execute_file(
+        path,
+        ExecuteQuery::default(),
+        Arc::new(ClientManager::new()),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.error.as_deref(), Some("No clients connected"));
+}
+
+#[tokio::test]
+async fn rejects_missing_file_and_bad_extension() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing.lua").to_string_lossy().to_string();
+    let wrong_extension = write_script(dir.path(), "notes.md", "# notes");
+    let client_manager = Arc::new(ClientManager::new());
+    let mut rx = fake_client(&client_manager).await;
+
+    for path in [String::new(), missing, wrong_extension] {
+        let (response, status) = execute_file(
+            path,
+            ExecuteQuery::default(),
+            client_manager.clone(),
+            Arc::new(ServerConfig::default()),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(!response.success);
+    }
+    assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn parses_file_lists() {
+    assert_eq!(parse_file_list(" a.lua \r\n\n b.lua\n"), ["a.lua", "b.lua"]);
+    assert_eq!(
+        parse_file_list(r#"["a.lua", " ", "b.lua"]"#),
+        ["a.lua", "b.lua"]
+    );
+    assert_eq!(parse_file_list("[x] a.lua"), ["[x] a.lua"]);
+    assert!(parse_file_list("  \n").is_empty());
+}
+
+#[tokio::test]
+async fn sends_multiple_files_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = write_script(dir.path(), "first.lua", "print(1)");
+    let second = write_script(dir.path(), "second.lua", "print(2)");
+    let client_manager = Arc::new(ClientManager::new());
+    let mut rx = fake_client(&client_manager).await;
+
+    let bodies = [
+        format!("{}\n{}", first, second),
+        serde_json::to_string(&[&first, &second]).unwrap(),
+    ];
+    for body in bodies {
+        let (response, status) = execute_file(
+            body,
+            ExecuteQuery::default(),
+            client_manager.clone(),
+            Arc::new(ServerConfig::default()),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(response.success);
+        let files = response.files.unwrap();
+        assert_eq!(files[1].path, second);
+        assert!(files.iter().all(|file| file.response.success));
+        assert_eq!(received_message(&mut rx).script, "print(1)");
+        assert_eq!(received_message(&mut rx).script, "print(2)");
+    }
+}
+
+#[tokio::test]
+async fn sends_no_files_when_one_is_invalid_unless_partial() {
+    let dir = tempfile::tempdir().unwrap();
+    let valid = write_script(dir.path(), "valid.lua", "print('valid')");
+    let invalid = write_script(dir.path(), "notes.md", "# notes");
+    let body = format!("{}\n{}", valid, invalid);
+    let client_manager = Arc::new(ClientManager::new());
+    let mut rx = fake_client(&client_manager).await;
+
+    let (response, status) = execute_file(
+        body.clone(),
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let files = response.files.unwrap();
+    assert!(files[0]
+        .response
+        .error
+        .as_deref()
+        .unwrap()
+        .starts_with("Not sent"));
+    assert!(files[1]
+        .response
+        .error
+        .as_deref()
+        .unwrap()
+        .starts_with("File must be"));
+    assert!(rx.try_recv().is_err());
+
+    let (response, status) = execute_file(
+        body,
+        ExecuteQuery {
+            partial: Some(true),
+            ..Default::default()
+        },
+        client_manager,
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::MULTI_STATUS);
+    assert!(!response.success);
+    assert_eq!(received_message(&mut rx).script, "print('valid')");
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn rejects_paths_escaping_the_script_root() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("scripts");
+    fs::create_dir(&root).unwrap();
+    let inside = write_script(&root, "inside.lua", "print('inside')");
+    write_script(dir.path(), "secret.lua", "print('secret')");
+    let config = Arc::new(ServerConfig {
+        script_root: Some(root.clone()),
+        ..Default::default()
+    });
+    let client_manager = Arc::new(ClientManager::new());
+    let mut rx = fake_client(&client_manager).await;
+
+    let traversal = root.join("..").join("secret.lua");
+    let (response, status) = execute_file(
+        traversal.to_string_lossy().to_string(),
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        config.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(response.error.unwrap().contains("outside the script root"));
+    assert!(rx.try_recv().is_err());
+
+    let (_, status) = execute_file(inside, ExecuteQuery::default(), client_manager, config).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(received_message(&mut rx).script, "print('inside')");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn rejects_symlinks_out_of_the_script_root() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("scripts");
+    fs::create_dir(&root).unwrap();
+    let secret = write_script(dir.path(), "secret.lua", "print('secret')");
+    let link = root.join("link.lua");
+    std::os::unix::fs::symlink(&secret, &link).unwrap();
+    let client_manager = Arc::new(ClientManager::new());
+    let mut rx = fake_client(&client_manager).await;
+
+    let (_, status) = execute_file(
+        link.to_string_lossy().to_string(),
+        ExecuteQuery::default(),
+        client_manager,
+        Arc::new(ServerConfig {
+            script_root: Some(root),
+            ..Default::default()
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(rx.try_recv().is_err());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn checks_the_file_a_symlink_resolves_to_under_the_script_root() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("scripts");
+    fs::create_dir(&root).unwrap();
+    let secret = write_script(&root, "secrets.env", "TOKEN=hunter2");
+    let disguised = root.join("allowed.lua");
+    std::os::unix::fs::symlink(&secret, &disguised).unwrap();
+    let dangling = root.join("dangling.lua");
+    std::os::unix::fs::symlink(root.join("missing.lua"), &dangling).unwrap();
+    let config = Arc::new(ServerConfig {
+        script_root: Some(root),
+        ..Default::default()
+    });
+    let client_manager = Arc::new(ClientManager::new());
+    let mut rx = fake_client(&client_manager).await;
+
+    let (response, status) = execute_file(
+        disguised.to_string_lossy().to_string(),
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        config.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(response.error.unwrap().starts_with("File must be"));
+
+    let (response, status) = execute_file(
+        dangling.to_string_lossy().to_string(),
+        ExecuteQuery::default(),
+        client_manager,
+        config,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(response.error.unwrap().contains("does not exist"));
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn refuses_a_file_that_is_already_executing() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "farm.lua", "farm()");
+    let other = write_script(dir.path(), "mine.lua", "mine()");
+    let client_manager = Arc::new(ClientManager::new());
+    let mut rx = fake_client(&client_manager).await;
+
+    // Another request for the same file, spelled differently, is still in flight
+    let in_flight = client_manager
+        .claim_files(&[format!("{}/./farm.lua", dir.path().display())])
+        .unwrap();
+    let (response, status) = execute_file(
+        path.clone(),
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(
+        response.error,
+        Some(format!("'{}' is already executing", path))
+    );
+    assert!(rx.try_recv().is_err());
+
+    // Other files go ahead, and the file is free again once the first request finishes
+    let (_, status) = execute_file(
+        other,
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    drop(in_flight);
+    let (_, status) = execute_file(
+        path,
+        ExecuteQuery::default(),
+        client_manager,
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn waits_for_a_turn_under_the_broadcast_limit() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "farm.lua", "farm()");
+    let client_manager = Arc::new(ClientManager::new().with_max_concurrent_broadcasts(1));
+    let mut rx = fake_client(&client_manager).await;
+
+    let permit = client_manager.broadcast_permit().await;
+    let request = tokio::spawn(execute_file(
+        path,
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    ));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!request.is_finished());
+    assert!(rx.try_recv().is_err());
+
+    // The queued request goes ahead as soon as the running one finishes
+    drop(permit);
+    let (_, status) = request.await.unwrap();
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(received_message(&mut rx).script, "farm()");
+}
+
+#[tokio::test]
+async fn runs_named_scripts_from_the_library() {
+    let dir = tempfile::tempdir().unwrap();
+    write_script(dir.path(), "autofarm.luau", "farm()");
+    write_script(dir.path(), "notes.md", "not a script");
+    let config = Arc::new(ServerConfig {
+        library: Some(dir.path().to_path_buf()),
+        ..Default::default()
+    });
+    let client_manager = Arc::new(ClientManager::new());
+    let mut rx = fake_client(&client_manager).await;
+
+    let (_, status) = run_library_script(
+        "autofarm".to_string(),
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        config.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let message = received_message(&mut rx);
+    assert_eq!(message.filename, "autofarm.luau");
+    assert_eq!(message.script, "farm()");
+
+    for (name, status) in [
+        ("notes", StatusCode::NOT_FOUND),
+        ("missing", StatusCode::NOT_FOUND),
+        ("..", StatusCode::BAD_REQUEST),
+        ("..\\secret", StatusCode::BAD_REQUEST),
+        ("sub/autofarm", StatusCode::BAD_REQUEST),
+    ] {
+        let (response, actual) = run_library_script(
+            name.to_string(),
+            ExecuteQuery::default(),
+            client_manager.clone(),
+            config.clone(),
+        )
+        .await;
+        assert_eq!(actual, status, "{}: {:?}", name, response.error);
+    }
+    assert!(rx.try_recv().is_err());
+
+    let (response, status) = run_library_script(
+        "autofarm".to_string(),
+        ExecuteQuery::default(),
+        client_manager,
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        response.error.as_deref(),
+        Some("No script library configured, start the server with --library")
+    );
+}
+
+#[tokio::test]
+async fn rejects_scripts_over_the_size_limit() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "big.lua", &"-".repeat(64));
+    let client_manager = Arc::new(ClientManager::new());
+    let mut rx = fake_client(&client_manager).await;
+    let config = Arc::new(ServerConfig {
+        max_script_bytes: 32,
+        ..Default::default()
+    });
+
+    let (response, status) = execute_file(
+        path,
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        config.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(!response.success);
+
+    let (_, status) = execute_code(
+        "-".repeat(64),
+        None,
+        ExecuteQuery::default(),
+        client_manager,
+        config,
+    )
+    .await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn rejects_scripts_that_are_not_utf8() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bad.lua");
+    fs::write(&path, b"print('caf\xe9')").unwrap();
+    let client_manager = Arc::new(ClientManager::new());
+    let mut rx = fake_client(&client_manager).await;
+
+    let (response, status) = execute_file(
+        path.to_string_lossy().to_string(),
+        ExecuteQuery::default(),
+        client_manager,
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.error.as_deref(), Some("Script is not valid UTF-8"));
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn accepts_configured_extensions_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let custom = write_script(dir.path(), "hello.GLUA", "print('hello')");
+    let default = write_script(dir.path(), "hello.lua", "print('hello')");
+    let client_manager = Arc::new(ClientManager::new());
+    let _rx = fake_client(&client_manager).await;
+    let config = Arc::new(ServerConfig {
+        allowed_extensions: vec![".glua".to_string()],
+        ..Default::default()
+    });
+
+    let (_, status) = execute_file(
+        custom,
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        config.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, status) = execute_file(default, ExecuteQuery::default(), client_manager, config).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn sends_only_to_clients_with_the_tag() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "farm.lua", "farm()");
+    let client_manager = Arc::new(ClientManager::new());
+    let (tx, mut farmer) = mpsc::channel(16);
+    let farmer_id = client_manager.register(tx).await;
+    client_manager
+        .set_tags(farmer_id, &["farming".to_string()])
+        .await;
+    let mut idle = fake_client(&client_manager).await;
+
+    let query = ExecuteQuery {
+        tag: Some("farming".to_string()),
+        ..Default::default()
+    };
+    let (response, status) = execute_file(
+        path.clone(),
+        query,
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response.selected_clients, Some(vec![farmer_id]));
+    assert_eq!(received_message(&mut farmer).script, "farm()");
+    assert!(idle.try_recv().is_err());
+
+    let query = ExecuteQuery {
+        tag: Some("mining".to_string()),
+        ..Default::default()
+    };
+    let (response, status) = execute_file(
+        path,
+        query,
+        client_manager,
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        response.error.as_deref(),
+        Some("No clients tagged 'mining' connected")
+    );
+}
+
+#[tokio::test]
+async fn skips_clients_below_the_minimum_protocol() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "new.lua", "useNewApi()");
+    let client_manager = Arc::new(ClientManager::new());
+    let mut unreported = fake_client(&client_manager).await;
+    let (tx, mut upgraded) = mpsc::channel(16);
+    let upgraded_id = client_manager.register(tx).await;
+    client_manager.set_protocol(upgraded_id, 2).await;
+
+    let query = ExecuteQuery {
+        min_protocol: Some(2),
+        ..Default::default()
+    };
+    let (response, status) = execute_file(
+        path.clone(),
+        query.clone(),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response.clients_reached, Some(1));
+    assert_eq!(response.skipped_clients, Some(vec![0]));
+    assert_eq!(received_message(&mut upgraded).script, "useNewApi()");
+    assert!(unreported.try_recv().is_err());
+
+    let (response, status) = execute_file_on(
+        path.clone(),
+        Some(0),
+        query,
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(
+        response.error.as_deref(),
+        Some("Client 0 speaks a protocol older than 2")
+    );
+
+    // Without a minimum every client gets the script
+    let (response, status) = execute_file(
+        path,
+        ExecuteQuery::default(),
+        client_manager,
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response.clients_reached, Some(2));
+    assert_eq!(response.skipped_clients, None);
+}
+
+#[tokio::test]
+async fn dry_run_validates_without_sending() {
+    let dir = tempfile::tempdir().unwrap();
+    let valid = write_script(dir.path(), "hello.lua", "print('hello')");
+    let invalid = write_script(dir.path(), "notes.md", "# notes");
+    let client_manager = Arc::new(ClientManager::new());
+    let mut rx = fake_client(&client_manager).await;
+    let dry_run = || ExecuteQuery {
+        dry_run: Some(true),
+        ..Default::default()
+    };
+
+    let (response, status) = execute_file(
+        valid,
+        dry_run(),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(response.success);
+    assert_eq!(response.clients_reached, None);
+    assert!(rx.try_recv().is_err());
+
+    let (_, status) = execute_file(
+        invalid,
+        dry_run(),
+        client_manager,
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn debounces_identical_scripts_within_the_window() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "hello.lua", "print('hello')");
+    let client_manager = Arc::new(ClientManager::new().with_debounce(Duration::from_secs(60)));
+    let config = Arc::new(ServerConfig::default());
+
+    // Nobody received the first attempt, so the retry isn't debounced
+    let (_, status) = execute_file(
+        path.clone(),
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        config.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+    let mut rx = fake_client(&client_manager).await;
+    for _ in 0..2 {
+        let (response, status) = execute_file(
+            path.clone(),
+            ExecuteQuery::default(),
+            client_manager.clone(),
+            config.clone(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(response.success);
+    }
+
+    assert_eq!(received_message(&mut rx).script, "print('hello')");
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn retries_are_not_debounced_when_every_send_failed() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "hello.lua", "print('hello')");
+    let client_manager = Arc::new(ClientManager::new().with_debounce(Duration::from_secs(60)));
+    let (tx, _rx) = mpsc::channel(1);
+    tx.try_send(Message::text("unread")).unwrap();
+    client_manager.register(tx).await;
+
+    for _ in 0..2 {
+        let (response, status) = execute_file(
+            path.clone(),
+            ExecuteQuery::default(),
+            client_manager.clone(),
+            Arc::new(ServerConfig::default()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+        assert_eq!(response.clients_reached, Some(0));
+    }
+}
+
+#[tokio::test]
+async fn debounces_only_repeat_broadcasts_of_the_same_message_type() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "hello.lua", "print('hello')");
+    let client_manager = Arc::new(ClientManager::new().with_debounce(Duration::from_secs(60)));
+    let config = Arc::new(ServerConfig::default());
+    let (first_tx, mut first) = mpsc::channel(16);
+    let first_id = client_manager.register(first_tx).await;
+    let (second_tx, mut second) = mpsc::channel(16);
+    let second_id = client_manager.register(second_tx).await;
+
+    // The same script sent to each client in turn reaches both
+    for id in [first_id, second_id] {
+        let (response, status) = execute_file_on(
+            path.clone(),
+            Some(id),
+            ExecuteQuery::default(),
+            client_manager.clone(),
+            config.clone(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.clients_reached, Some(1));
+    }
+    assert_eq!(received_message(&mut first).script, "print('hello')");
+    assert_eq!(received_message(&mut second).script, "print('hello')");
+
+    // A broadcast under another message type isn't a repeat of the first
+    for msg_type in ["execute", "run"] {
+        let (response, _) = execute_file(
+            path.clone(),
+            ExecuteQuery {
+                msg_type: Some(msg_type.to_string()),
+                ..Default::default()
+            },
+            client_manager.clone(),
+            config.clone(),
+        )
+        .await;
+        assert_eq!(response.clients_reached, Some(2));
+    }
+    assert_eq!(received_message(&mut first).msg_type, "execute");
+    assert_eq!(received_message(&mut first).msg_type, "run");
+    assert!(first.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn applies_message_type_override() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "hello.lua", "print('hello')");
+    let client_manager = Arc::new(ClientManager::new());
+    let mut rx = fake_client(&client_manager).await;
+
+    let (_, status) = execute_file(
+        path.clone(),
+        ExecuteQuery {
+            msg_type: Some("ping".to_string()),
+            ..Default::default()
+        },
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(rx.try_recv().is_err());
+
+    let (_, status) = execute_file(
+        path,
+        ExecuteQuery {
+            msg_type: Some("loadstring".to_string()),
+            ..Default::default()
+        },
+        client_manager,
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(received_message(&mut rx).msg_type, "loadstring");
+}
+
+#[tokio::test]
+async fn sends_to_a_seeded_sample_of_clients() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "canary.lua", "print('canary')");
+    let client_manager = Arc::new(ClientManager::new());
+    let mut receivers = Vec::new();
+    for _ in 0..10 {
+        receivers.push(fake_client(&client_manager).await);
+    }
+    let query = || ExecuteQuery {
+        sample: Some(0.25),
+        seed: Some(7),
+        ..Default::default()
+    };
+
+    let (first, status) = execute_file(
+        path.clone(),
+        query(),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+    let (second, _) = execute_file(
+        path,
+        query(),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    let sampled = first.selected_clients.unwrap();
+    assert_eq!(sampled.len(), 3);
+    assert_eq!(first.clients_reached, Some(3));
+    assert_eq!(second.selected_clients.unwrap(), sampled);
+    for (id, rx) in receivers.iter_mut().enumerate() {
+        let expected = if sampled.contains(&id) { 2 } else { 0 };
+        let mut received = 0;
+        while rx.try_recv().is_ok() {
+            received += 1;
+        }
+        assert_eq!(received, expected);
+    }
+}
+
+#[tokio::test]
+async fn rejects_out_of_range_sample() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "canary.lua", "print('canary')");
+
+    for sample in [0.0, 1.5, -0.1] {
+        let (_, status) = execute_file(
+            path.clone(),
+            ExecuteQuery {
+                sample: Some(sample),
+                ..Default::default()
+            },
+            Arc::new(ClientManager::new()),
+            Arc::new(ServerConfig::default()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn targets_least_recently_targeted_clients() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "job.lua", "print('job')");
+    let client_manager = Arc::new(ClientManager::new());
+    let mut receivers = Vec::new();
+    for _ in 0..3 {
+        receivers.push(fake_client(&client_manager).await);
+    }
+    let lru = |count| ExecuteQuery {
+        target: Some("lru".to_string()),
+        count: Some(count),
+        ..Default::default()
+    };
+
+    let mut selections = Vec::new();
+    for count in [2, 2, 1] {
+        let (response, status) = execute_file(
+            path.clone(),
+            lru(count),
+            client_manager.clone(),
+            Arc::new(ServerConfig::default()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        selections.push(response.selected_clients.unwrap());
+    }
+
+    assert_eq!(selections, vec![vec![0, 1], vec![2, 0], vec![1]]);
+    let received: Vec<usize> = receivers
+        .iter_mut()
+        .map(|rx| std::iter::from_fn(|| rx.try_recv().ok()).count())
+        .collect();
+    assert_eq!(received, vec![2, 2, 1]);
+}
+
+#[tokio::test]
+async fn counts_acks_from_clients_that_ran_the_script() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "hello.lua", "print('hello')");
+    let client_manager = Arc::new(ClientManager::new());
+    let (tx, mut acking) = mpsc::channel(16);
+    let acking_id = client_manager.register(tx).await;
+    let _silent = fake_client(&client_manager).await;
+
+    // Ack as soon as the script arrives, like a real executor would after running it
+    let acker = client_manager.clone();
+    tokio::spawn(async move {
+        let message = acking.recv().await.unwrap();
+        let message: ExecuteMessage = serde_json::from_str(message.to_str().unwrap()).unwrap();
+        acker.record_ack(acking_id, message.id);
+    });
+
+    let (response, status) = execute_file(
+        path,
+        ExecuteQuery {
+            wait_for_acks: Some(true),
+            ..Default::default()
+        },
+        client_manager,
+        Arc::new(ServerConfig {
+            ack_timeout_secs: 1,
+            ..Default::default()
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response.clients_reached, Some(2));
+    assert_eq!(response.acknowledged, Some(1));
+}
+
+#[tokio::test]
+async fn returns_partial_acks_when_the_execute_timeout_runs_out() {
+    let client_manager = Arc::new(ClientManager::new());
+    let (tx, mut acking) = mpsc::channel(16);
+    let acking_id = client_manager.register(tx).await;
+    let _silent = fake_client(&client_manager).await;
+
+    let acker = client_manager.clone();
+    let execution_id = tokio::spawn(async move {
+        let message = acking.recv().await.unwrap();
+        let message: ExecuteMessage = serde_json::from_str(message.to_str().unwrap()).unwrap();
+        acker.record_ack(acking_id, message.id);
+        message.id
+    });
+
+    // The ack timeout is longer than the execute timeout, which the flags wouldn't allow
+    let reply = handle_execute_code(
+        "print('hello')".into(),
+        None,
+        ExecuteQuery {
+            wait_for_acks: Some(true),
+            ..Default::default()
+        },
+        None,
+        client_manager.clone(),
+        Arc::new(ServerConfig {
+            execute_timeout_secs: 1,
+            ack_timeout_secs: 5,
+            ..Default::default()
+        }),
+    )
+    .await;
+
+    let response = warp::Reply::into_response(reply.unwrap());
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    let body = warp::hyper::body::to_bytes(response.into_body())
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["clients_reached"], 2);
+    assert_eq!(body["acknowledged"], 1);
+
+    // The request stopped waiting, so later acks for it go nowhere
+    let execution_id = execution_id.await.unwrap();
+    assert!(!client_manager.record_ack(acking_id, execution_id));
+}
+
+#[tokio::test]
+async fn broadcasts_inline_code_with_default_filename() {
+    let client_manager = Arc::new(ClientManager::new());
+    let mut client = fake_client(&client_manager).await;
+
+    let (response, status) = execute_code(
+        "print('inline')".to_string(),
+        None,
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response.clients_reached, Some(1));
+    let message = received_message(&mut client);
+    assert_eq!(message.script, "print('inline')");
+    assert_eq!(message.filename, "inline.lua");
+
+    let (response, status) = execute_code(
+        "  \n".to_string(),
+        Some("named.lua".to_string()),
+        ExecuteQuery::default(),
+        client_manager,
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.error.as_deref(), Some("No code provided"));
+}
+
+#[tokio::test]
+async fn sends_to_a_single_client_by_id() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "hello.lua", "print('hello')");
+    let client_manager = Arc::new(ClientManager::new());
+    let mut other = fake_client(&client_manager).await;
+    let mut chosen = fake_client(&client_manager).await;
+
+    let (response, status) = execute_file_on(
+        path.clone(),
+        Some(1),
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response.clients_reached, Some(1));
+    assert_eq!(received_message(&mut chosen).filename, "hello.lua");
+    assert!(other.try_recv().is_err());
+
+    let (response, status) = execute_file_on(
+        path,
+        Some(7),
+        ExecuteQuery::default(),
+        client_manager,
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(response.error.as_deref(), Some("Client 7 not connected"));
+}
+
+#[tokio::test]
+async fn sends_to_the_client_in_a_slot() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "hello.lua", "print('hello')");
+    let client_manager = Arc::new(ClientManager::new());
+    let (tx, _first) = mpsc::channel(16);
+    let first_id = client_manager.register(tx).await;
+    let mut second = fake_client(&client_manager).await;
+    let mut third = fake_client(&client_manager).await;
+    let slot = |slot| ExecuteQuery {
+        slot: Some(slot),
+        ..Default::default()
+    };
+
+    let (_, status) = execute_file(
+        path.clone(),
+        slot(1),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(received_message(&mut second).filename, "hello.lua");
+    assert!(third.try_recv().is_err());
+
+    // Slots close up when a client leaves, so the third client moves into slot 1
+    client_manager.unregister(first_id).await;
+    let slots: Vec<usize> = client_manager
+        .list_clients()
+        .await
+        .iter()
+        .map(|info| info.slot)
+        .collect();
+    assert_eq!(slots, vec![0, 1]);
+    let (_, status) = execute_file(
+        path.clone(),
+        slot(1),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(received_message(&mut third).filename, "hello.lua");
+
+    let (response, status) = execute_file(
+        path,
+        slot(2),
+        client_manager,
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(response.error.as_deref(), Some("No client in slot 2"));
+}
+
+#[tokio::test]
+async fn queues_script_for_next_client_when_none_connected() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_script(dir.path(), "late.lua", "print('late')");
+    let client_manager = Arc::new(ClientManager::new().with_queue(Duration::from_secs(60)));
+
+    let (response, status) = execute_file(
+        path,
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert!(response.success);
+    let mut first = fake_client(&client_manager).await;
+    let mut second = fake_client(&client_manager).await;
+    assert_eq!(received_message(&mut first).filename, "late.lua");
+    assert!(second.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn replays_last_script_to_reconnecting_and_existing_clients() {
+    let client_manager = Arc::new(ClientManager::new().with_replay_last());
+    let (_, status) = replay(None, &client_manager).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let mut existing = fake_client(&client_manager).await;
+    let (_, status) = execute_code(
+        "print('again')".to_string(),
+        Some("again.lua".to_string()),
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(received_message(&mut existing).filename, "again.lua");
+
+    // A client reconnecting after a game reload is caught up straight away
+    let mut reconnected = fake_client(&client_manager).await;
+    assert_eq!(received_message(&mut reconnected).script, "print('again')");
+
+    let (response, status) = replay(None, &client_manager).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response.clients_reached, Some(2));
+    for rx in [&mut existing, &mut reconnected] {
+        assert_eq!(received_message(rx).filename, "again.lua");
+    }
+}
+
+#[tokio::test]
+async fn broadcasts_control_messages_verbatim() {
+    let client_manager = Arc::new(ClientManager::new());
+    let mut rx = fake_client(&client_manager).await;
+
+    let body = br#"{"type":"stop_all","reason":"maintenance"}"#;
+    let (response, status) = broadcast_control(body, &client_manager).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response.clients_reached, Some(1));
+    let message = rx.try_recv().unwrap();
+    assert_eq!(message.to_str().unwrap().as_bytes(), body);
+
+    for invalid in [
+        &b"not json"[..],
+        b"[1, 2]",
+        br#"{"reason":"x"}"#,
+        br#"{"type":7}"#,
+    ] {
+        let (response, status) = broadcast_control(invalid, &client_manager).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(!response.success);
+    }
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn keeps_a_bounded_history_of_broadcasts() {
+    let client_manager = Arc::new(ClientManager::new().with_history_size(2));
+    let _rx = fake_client(&client_manager).await;
+    let config = Arc::new(ServerConfig::default());
+
+    for script in ["print(1)", "print(2)", &"x".repeat(500)] {
+        execute_code(
+            script.to_string(),
+            None,
+            ExecuteQuery::default(),
+            client_manager.clone(),
+            config.clone(),
+        )
+        .await;
+    }
+
+    let history = client_manager.history().await;
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].script_preview, "print(2)");
+    assert_eq!(history[1].script_bytes, 500);
+    assert!(history[1].script_preview.len() < 500);
+    assert_eq!(history[1].clients_reached, 1);
+    assert_eq!(history[1].total_clients, 1);
+}
+
+#[tokio::test]
+async fn preserves_line_endings_byte_for_byte() {
+    let dir = tempfile::tempdir().unwrap();
+    let script = "local s = [[a\r\nb]]\r\nprint(s)\r\n";
+    let path = write_script(dir.path(), "crlf.lua", script);
+    let client_manager = Arc::new(ClientManager::new());
+    let mut rx = fake_client(&client_manager).await;
+
+    let (_, status) = execute_file(
+        path,
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let message = received_message(&mut rx);
+    assert_eq!(message.script, script);
+    assert_eq!(message.line_ending, "crlf");
+    assert_eq!(message.byte_length, script.len());
+
+    for (script, expected) in [("print(1)", "none"), ("a\nb\r\n", "mixed"), ("a\nb", "lf")] {
+        execute_code(
+            script.to_string(),
+            None,
+            ExecuteQuery::default(),
+            client_manager.clone(),
+            Arc::new(ServerConfig::default()),
+        )
+        .await;
+        assert_eq!(received_message(&mut rx).line_ending, expected);
+    }
+}
+
+#[tokio::test]
+async fn sends_allowed_bytecode_as_base64() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("compiled.luac");
+    let bytecode = [0x1b, 0x4c, 0x75, 0x61, 0xff, 0x00];
+    fs::write(&path, bytecode).unwrap();
+    let path = path.to_string_lossy().to_string();
+    let client_manager = Arc::new(ClientManager::new());
+    let mut rx = fake_client(&client_manager).await;
+
+    // Bytecode is only accepted once its extension is allowed
+    let (_, status) = execute_file(
+        path.clone(),
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        Arc::new(ServerConfig::default()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let config = ServerConfig {
+        allowed_extensions: vec![".lua".to_string(), ".luac".to_string()],
+        ..Default::default()
+    };
+    let (_, status) = execute_file(
+        path.clone(),
+        ExecuteQuery::default(),
+        client_manager.clone(),
+        Arc::new(config),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let message = received_message(&mut rx);
+    assert_eq!(message.msg_type, "execute_binary");
+    assert_eq!(message.script, "");
+    assert_eq!(message.script_b64.as_deref(), Some("G0x1Yf8A"));
+    assert_eq!(message.byte_length, bytecode.len());
+
+    let config = ServerConfig {
+        allowed_extensions: vec![".luac".to_string()],
+        max_script_bytes: 4,
+        ..Default::default()
+    };
+    let (_, status) = execute_file(
+        path,
+        ExecuteQuery::default(),
+        client_manager,
+        Arc::new(config),
+    )
+    .await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+}
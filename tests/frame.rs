@@ -0,0 +1,76 @@
+use std::io::Read;
+
+use flate2::read::GzDecoder;
+
+use roblox_executor_proxy::frame::{
+    encode_frame, encode_frames, COMPRESS_MIN_BYTES, GZIP_FRAME_HEADER,
+};
+use roblox_executor_proxy::types::ChunkMessage;
+
+#[test]
+fn large_messages_compress_to_binary_frames() {
+    let json = format!(
+        r#"{{"type":"execute","script":"{}"}}"#,
+        "print('hi')".repeat(COMPRESS_MIN_BYTES)
+    );
+
+    let frame = encode_frame(&json, true);
+    assert!(frame.is_binary());
+
+    let bytes = frame.as_bytes();
+    assert_eq!(bytes[0], GZIP_FRAME_HEADER);
+    assert!(bytes.len() < json.len());
+
+    let mut decoded = String::new();
+    GzDecoder::new(&bytes[1..])
+        .read_to_string(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, json);
+}
+
+#[test]
+fn small_or_uncompressed_messages_stay_text() {
+    let small = r#"{"type":"execute","script":"print('hi')"}"#;
+    assert_eq!(encode_frame(small, true).to_str(), Ok(small));
+
+    let large = "x".repeat(COMPRESS_MIN_BYTES * 2);
+    assert!(encode_frame(&large, false).is_text());
+}
+
+#[test]
+fn chunked_messages_reassemble_to_the_original() {
+    let json = format!(
+        r#"{{"type":"execute","script":"{}"}}"#,
+        "print('héllo ✓')\r\n".repeat(50)
+    );
+
+    let frames = encode_frames(&json, 7, Some(64), false);
+    let chunks: Vec<ChunkMessage> = frames
+        .iter()
+        .map(|frame| serde_json::from_str(frame.to_str().unwrap()).unwrap())
+        .collect();
+
+    let total = chunks[0].total;
+    assert!(total > 1);
+    assert_eq!(chunks.len(), total + 1);
+
+    let mut reassembled = String::new();
+    for (seq, chunk) in chunks.iter().enumerate() {
+        assert_eq!(chunk.msg_type, "execute_chunk");
+        assert_eq!((chunk.id, chunk.seq, chunk.total), (7, seq, total));
+        assert!(chunk.data.len() <= 64);
+        reassembled.push_str(&chunk.data);
+    }
+    assert!(chunks[total].data.is_empty());
+    assert_eq!(reassembled.as_bytes(), json.as_bytes());
+}
+
+#[test]
+fn messages_within_the_chunk_size_are_sent_whole() {
+    let json = r#"{"type":"execute","script":"print('hi')"}"#;
+    for chunk_size in [None, Some(json.len())] {
+        let frames = encode_frames(json, 1, chunk_size, false);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].to_str(), Ok(json));
+    }
+}
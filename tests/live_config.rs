@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use roblox_executor_proxy::auth::handle_rejection;
+use roblox_executor_proxy::config::ServerConfig;
+use roblox_executor_proxy::live_config::{body_size_limit, LiveConfig};
+use warp::Filter;
+
+#[tokio::test]
+async fn apply_swaps_only_extensions_and_size_limit() {
+    let live = LiveConfig::new(ServerConfig {
+        api_key: Some("secret".to_string()),
+        ..Default::default()
+    });
+    let before = live.get().await;
+
+    let updated = live
+        .apply(ServerConfig {
+            allowed_extensions: vec![".luau".to_string()],
+            max_script_bytes: 64,
+            http_port: 1,
+            ..Default::default()
+        })
+        .await;
+
+    assert_eq!(updated.allowed_extensions, vec![".luau".to_string()]);
+    assert_eq!(updated.max_script_bytes, 64);
+    assert_eq!(updated.http_port, before.http_port);
+    assert_eq!(updated.api_key.as_deref(), Some("secret"));
+    assert_eq!(live.get().await.max_script_bytes, 64);
+
+    // Snapshots taken before the reload are left as they were
+    assert_ne!(before.max_script_bytes, 64);
+}
+
+#[tokio::test]
+async fn body_size_limit_follows_reloads() {
+    let live = Arc::new(LiveConfig::new(ServerConfig::default()));
+    let filter = body_size_limit(live.clone()).map(warp::reply);
+
+    let request = || warp::test::request().method("POST").body("print('hello')");
+    assert!(request().matches(&filter).await);
+
+    live.apply(ServerConfig {
+        max_script_bytes: 4,
+        ..Default::default()
+    })
+    .await;
+    assert!(!request().matches(&filter).await);
+}
+
+#[tokio::test]
+async fn body_size_limit_requires_a_content_length() {
+    let live = Arc::new(LiveConfig::new(ServerConfig::default()));
+    let filter = body_size_limit(live)
+        .map(warp::reply)
+        .recover(handle_rejection);
+
+    // Without a body the test client sends no Content-Length, just like a chunked upload
+    let reply = warp::test::request().method("POST").reply(&filter).await;
+    assert_eq!(reply.status(), warp::http::StatusCode::LENGTH_REQUIRED);
+
+    let reply = warp::test::request()
+        .method("POST")
+        .body("print('hello')")
+        .reply(&filter)
+        .await;
+    assert_eq!(reply.status(), warp::http::StatusCode::OK);
+}
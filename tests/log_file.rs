@@ -0,0 +1,36 @@
+use std::fs;
+
+use roblox_executor_proxy::log_file::LogFile;
+
+#[test]
+fn appends_and_rotates_past_the_size_limit() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("proxy.log");
+    fs::write(&path, "earlier run\n").unwrap();
+
+    let mut log_file = LogFile::open(&path, 32).unwrap();
+    log_file.write_line("first line").unwrap();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "earlier run\nfirst line\n"
+    );
+
+    // 23 bytes already written, so another 11 byte line starts a fresh file
+    log_file.write_line("second line").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "second line\n");
+    assert_eq!(
+        fs::read_to_string(log_file.rotated_path()).unwrap(),
+        "earlier run\nfirst line\n"
+    );
+
+    // Lines longer than the limit are still written whole, in a file of their own
+    log_file.write_line(&"x".repeat(40)).unwrap();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        format!("{}\n", "x".repeat(40))
+    );
+    assert_eq!(
+        fs::read_to_string(log_file.rotated_path()).unwrap(),
+        "second line\n"
+    );
+}
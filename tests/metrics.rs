@@ -0,0 +1,26 @@
+use roblox_executor_proxy::metrics::Metrics;
+
+#[test]
+fn renders_counters_in_prometheus_text_format() {
+    let metrics = Metrics::default();
+    metrics.record_execute();
+    metrics.record_execute();
+    metrics.record_failed_broadcast();
+
+    let output = metrics.render_prometheus(3, 120);
+    let samples: Vec<&str> = output
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    assert_eq!(
+        samples,
+        [
+            "rbxproxy_connected_clients 3",
+            "rbxproxy_executes_total 2",
+            "rbxproxy_broadcast_failures_total 1",
+            "rbxproxy_uptime_seconds 120",
+        ]
+    );
+    assert!(output.contains("# TYPE rbxproxy_executes_total counter\n"));
+    assert!(output.contains("# TYPE rbxproxy_connected_clients gauge\n"));
+}
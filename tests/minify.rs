@@ -0,0 +1,43 @@
+use roblox_executor_proxy::minify::minify_lua;
+
+#[test]
+fn strips_comments_and_indentation() {
+    let source = "-- setup\nlocal x = 1 -- counter\n\tif x then\n    print(x)   \n  end\n";
+    assert_eq!(
+        minify_lua(source),
+        "\nlocal x = 1\nif x then\nprint(x)\nend\n"
+    );
+}
+
+#[test]
+fn keeps_line_numbers_across_block_comments() {
+    let source = "--[[ header\nspanning lines ]]\nlocal a = 1 --[==[ ]] still ]==] + 2\nreturn a";
+    assert_eq!(minify_lua(source), "\n\nlocal a = 1 + 2\nreturn a");
+}
+
+#[test]
+fn leaves_strings_containing_dashes_alone() {
+    let source = concat!(
+        "print(\"-- not a comment\") -- a comment\n",
+        "print('it''s -- here', 'a\\'--b')\n",
+        "local s = [[\n    -- kept, indentation too\n]]\n",
+        "local t = [=[ ]] -- ]=] -- gone\n",
+    );
+    assert_eq!(
+        minify_lua(source),
+        concat!(
+            "print(\"-- not a comment\")\n",
+            "print('it''s -- here', 'a\\'--b')\n",
+            "local s = [[\n    -- kept, indentation too\n]]\n",
+            "local t = [=[ ]] -- ]=]\n",
+        )
+    );
+}
+
+#[test]
+fn keeps_crlf_line_endings_and_subtraction() {
+    assert_eq!(
+        minify_lua("  local y = 5 - -3 --neg\r\n  return t[1]\r\n"),
+        "local y = 5 - -3\r\nreturn t[1]\r\n"
+    );
+}
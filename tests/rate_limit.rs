@@ -0,0 +1,19 @@
+use std::net::IpAddr;
+
+use roblox_executor_proxy::rate_limit::RateLimiter;
+
+#[tokio::test]
+async fn limits_each_ip_separately() {
+    let limiter = RateLimiter::new(2);
+    let first: IpAddr = "10.0.0.1".parse().unwrap();
+    let second: IpAddr = "10.0.0.2".parse().unwrap();
+
+    assert!(limiter.check(first).await.is_ok());
+    assert!(limiter.check(first).await.is_ok());
+
+    // Two per minute refill one token every 30 seconds
+    let retry_after = limiter.check(first).await.unwrap_err();
+    assert!((29..=30).contains(&retry_after));
+
+    assert!(limiter.check(second).await.is_ok());
+}
@@ -0,0 +1,26 @@
+use roblox_executor_proxy::config::TimestampFormat;
+use roblox_executor_proxy::timestamp::{self, Timestamp};
+
+// The format is process-wide, so every case lives in one test
+#[test]
+fn formats_timestamps_in_the_configured_style() {
+    assert!(matches!(Timestamp::now(), Timestamp::Rfc3339(text) if text.contains('T')));
+
+    timestamp::set_format(TimestampFormat::EpochMs);
+    let Timestamp::Epoch(millis) = Timestamp::now() else {
+        panic!("expected epoch milliseconds");
+    };
+    assert!(millis > 1_700_000_000_000);
+    assert_eq!(
+        serde_json::to_string(&Timestamp::Epoch(millis)).unwrap(),
+        millis.to_string()
+    );
+
+    timestamp::set_format(TimestampFormat::EpochS);
+    let Timestamp::Epoch(secs) = Timestamp::now() else {
+        panic!("expected epoch seconds");
+    };
+    assert!((secs - millis / 1000).abs() <= 1);
+
+    assert!("epoch_us".parse::<TimestampFormat>().is_err());
+}
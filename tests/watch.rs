@@ -0,0 +1,38 @@
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+
+use roblox_executor_proxy::client_manager::ClientManager;
+use roblox_executor_proxy::config::ServerConfig;
+use roblox_executor_proxy::live_config::LiveConfig;
+use roblox_executor_proxy::types::ExecuteMessage;
+use roblox_executor_proxy::watch::spawn_watcher;
+use tokio::sync::mpsc;
+
+#[tokio::test]
+async fn broadcasts_changed_scripts_in_watched_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let client_manager = Arc::new(ClientManager::new());
+    let (tx, mut rx) = mpsc::channel(16);
+    client_manager.register(tx).await;
+
+    let watcher = spawn_watcher(
+        dir.path(),
+        client_manager,
+        Arc::new(LiveConfig::new(ServerConfig::default())),
+    )
+    .unwrap();
+
+    fs::write(dir.path().join("notes.md"), "ignored").unwrap();
+    fs::write(dir.path().join("hello.lua"), "print('hello')").unwrap();
+
+    let message = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("watched script should be broadcast")
+        .unwrap();
+    let message: ExecuteMessage = serde_json::from_str(message.to_str().unwrap()).unwrap();
+    assert_eq!(message.filename, "hello.lua");
+    assert_eq!(message.script, "print('hello')");
+
+    watcher.abort();
+}
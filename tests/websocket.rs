@@ -0,0 +1,64 @@
+use roblox_executor_proxy::handlers::{parse_client_message, reject_ws_upgrade};
+use roblox_executor_proxy::types::ClientMessage;
+use warp::http::StatusCode;
+
+fn upgrade(path: &str) -> warp::test::RequestBuilder {
+    warp::test::request()
+        .path(path)
+        .header("connection", "upgrade")
+        .header("upgrade", "websocket")
+        .header("sec-websocket-version", "13")
+        .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+}
+
+#[tokio::test]
+async fn upgrades_on_wrong_paths_get_404() {
+    let filter = reject_ws_upgrade("/".to_string());
+
+    let response = upgrade("/foo").reply(&filter).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(
+        body["error"],
+        "No WebSocket endpoint at /foo, connect to / instead"
+    );
+}
+
+#[tokio::test]
+async fn plain_requests_are_left_to_other_routes() {
+    let filter = reject_ws_upgrade("/".to_string());
+    assert!(!warp::test::request().path("/foo").matches(&filter).await);
+}
+
+#[test]
+fn parses_client_messages_strictly() {
+    assert!(matches!(
+        parse_client_message(r#"{"type":"pong","ts":1700000000000}"#),
+        Ok(ClientMessage::Pong {
+            ts: Some(1700000000000)
+        })
+    ));
+    assert!(matches!(
+        parse_client_message(r#"{"type":"list_clients"}"#),
+        Ok(ClientMessage::ListClients)
+    ));
+
+    // Errors name the field at fault, whether or not it is required
+    assert_eq!(
+        parse_client_message(r#"{"type":"pong","ts":"notanumber"}"#).err(),
+        Some(r#"pong field `ts`: invalid type: string "notanumber", expected i64"#.to_string())
+    );
+    assert_eq!(
+        parse_client_message(r#"{"type":"ack","id":"7","status":"ok"}"#).err(),
+        Some(r#"ack field `id`: invalid type: string "7", expected u64"#.to_string())
+    );
+    assert_eq!(
+        parse_client_message(r#"{"type":"ack","status":"ok"}"#).err(),
+        Some("missing field `id`".to_string())
+    );
+    assert!(parse_client_message(r#"{"type":"dance"}"#)
+        .err()
+        .unwrap()
+        .starts_with("unknown variant `dance`"));
+    assert!(parse_client_message("pong").is_err());
+}